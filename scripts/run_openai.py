@@ -1,7 +1,7 @@
 from pathlib import Path
 from openai import OpenAI
 
-url = "http://localhost:3000/v1/audio/speech"
+url = "http://localhost:3000/v1"
 
 
 client = OpenAI(base_url=url, api_key="sfrhg453656")
@@ -0,0 +1,336 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use crate::tts::koko::{TTSKoko, TTSOpts};
+
+/// Text synthesized by every benchmark request, three sentences of
+/// ordinary narration.
+pub const BENCH_TEXT: &str = "The quick brown fox jumps over the lazy dog. \
+    Kokoro is a text to speech model with 82 million parameters. \
+    It runs comfortably on a laptop, and faster still on a server.";
+
+/// Throughput of one benchmark configuration.
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub label: String,
+    pub requests: usize,
+    /// Wall time for all requests, in seconds.
+    pub elapsed: f32,
+    /// Total length of the synthesized audio, in seconds.
+    pub audio_seconds: f32,
+    /// How the audio compares to a reference, see `compare`.
+    pub quality: Option<Quality>,
+}
+
+impl BenchResult {
+    pub fn requests_per_sec(&self) -> f32 {
+        self.requests as f32 / self.elapsed
+    }
+
+    /// Seconds of audio produced per second of wall time.
+    pub fn audio_per_sec(&self) -> f32 {
+        self.audio_seconds / self.elapsed
+    }
+}
+
+/// Synthesizes `txt` `requests` times from `concurrency` threads sharing
+/// `tts`, after one warm-up run that is not counted.
+#[allow(clippy::too_many_arguments)]
+pub fn run_throughput(
+    label: &str,
+    tts: &TTSKoko,
+    txt: &str,
+    lan: &str,
+    style_name: &str,
+    opts: &TTSOpts,
+    requests: usize,
+    concurrency: usize,
+) -> Result<BenchResult, String> {
+    tts.tts(txt, lan, style_name, opts).map_err(|e| e.to_string())?;
+
+    let next = AtomicUsize::new(0);
+    let audio_seconds = Mutex::new(0.0f32);
+    let start = Instant::now();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..concurrency.max(1))
+            .map(|_| {
+                scope.spawn(|| -> Result<(), String> {
+                    while next.fetch_add(1, Ordering::Relaxed) < requests {
+                        let audio = tts.tts(txt, lan, style_name, opts).map_err(|e| e.to_string())?;
+                        *audio_seconds.lock().unwrap() += audio.duration;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().map_err(|_| "benchmark thread panicked".to_string())?)
+    })?;
+
+    Ok(BenchResult {
+        label: label.to_string(),
+        requests,
+        elapsed: start.elapsed().as_secs_f32(),
+        audio_seconds: audio_seconds.into_inner().unwrap(),
+        quality: None,
+    })
+}
+
+/// Samples per analysis frame of `spectral_distance`, about 21 ms at 24 kHz.
+const FRAME_LEN: usize = 512;
+
+/// Samples between the starts of two analysis frames.
+const FRAME_HOP: usize = 256;
+
+/// Number of mel bands the spectrum of a frame is summed into.
+const MEL_BANDS: usize = 40;
+
+/// Band power below which bands count as silent, about 80 dB under a
+/// full-scale tone, so that near silence compares as equal.
+const POWER_FLOOR: f64 = 1e-4;
+
+/// How a synthesis compares to the reference one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quality {
+    /// Spectral distance to the reference in dB, see `spectral_distance`.
+    pub distance_db: f32,
+    /// Change in length, as a fraction of the reference length.
+    pub length_change: f32,
+}
+
+/// Compares `audio` to `reference`: how different it sounds, and
+/// separately how much longer or shorter it is.
+pub fn compare(reference: &[f32], audio: &[f32], sample_rate: u32) -> Quality {
+    let length_change = match reference.len() {
+        0 => 0.0,
+        len => (audio.len() as f64 / len as f64 - 1.0) as f32,
+    };
+    Quality {
+        distance_db: spectral_distance(reference, audio, sample_rate),
+        length_change,
+    }
+}
+
+/// How different `audio` sounds from `reference`, as the root mean square
+/// difference of their log-mel spectra in dB, averaged over frames. Each
+/// frame of `reference` is compared with the frame of `audio` at the same
+/// fraction of its length, so speech that is only timed differently, which
+/// shifts every sample after it, is not counted as different. Identical
+/// audio gives 0, differences of about 1 dB or less are hard to hear.
+pub fn spectral_distance(reference: &[f32], audio: &[f32], sample_rate: u32) -> f32 {
+    let analyzer = MelAnalyzer::new(sample_rate);
+    let reference = analyzer.spectra(reference);
+    let audio = analyzer.spectra(audio);
+    if reference.is_empty() || audio.is_empty() {
+        return if reference.len() == audio.len() { 0.0 } else { f32::INFINITY };
+    }
+
+    let total: f64 = reference
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let other = &audio[i * audio.len() / reference.len()];
+            let squares: f64 = frame.iter().zip(other).map(|(a, b)| (a - b).powi(2)).sum();
+            (squares / MEL_BANDS as f64).sqrt()
+        })
+        .sum();
+    (total / reference.len() as f64) as f32
+}
+
+/// Log-mel spectra of Hann-windowed frames, by a plain DFT, which is fast
+/// enough for the few seconds of audio a benchmark compares.
+struct MelAnalyzer {
+    window: Vec<f64>,
+    /// Cosines and sines of the DFT, one row per frequency bin.
+    cos: Vec<Vec<f64>>,
+    sin: Vec<Vec<f64>>,
+    /// Triangular filter of each mel band, as (bin, weight) pairs.
+    filters: Vec<Vec<(usize, f64)>>,
+}
+
+impl MelAnalyzer {
+    fn new(sample_rate: u32) -> Self {
+        use std::f64::consts::PI;
+
+        let bins = FRAME_LEN / 2 + 1;
+        let window = (0..FRAME_LEN)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f64 / FRAME_LEN as f64).cos())
+            .collect();
+        let angle = |k: usize, n: usize| 2.0 * PI * (k * n % FRAME_LEN) as f64 / FRAME_LEN as f64;
+        let cos = (0..bins).map(|k| (0..FRAME_LEN).map(|n| angle(k, n).cos()).collect()).collect();
+        let sin = (0..bins).map(|k| (0..FRAME_LEN).map(|n| angle(k, n).sin()).collect()).collect();
+
+        let mel = |hz: f64| 2595.0 * (1.0 + hz / 700.0).log10();
+        let hz = |mel: f64| 700.0 * (10f64.powf(mel / 2595.0) - 1.0);
+        let top = mel(sample_rate as f64 / 2.0);
+        let edges: Vec<f64> = (0..MEL_BANDS + 2).map(|i| hz(top * i as f64 / (MEL_BANDS + 1) as f64)).collect();
+        let filters = edges
+            .windows(3)
+            .map(|band| {
+                let (low, center, high) = (band[0], band[1], band[2]);
+                (0..bins)
+                    .filter_map(|k| {
+                        let f = k as f64 * sample_rate as f64 / FRAME_LEN as f64;
+                        let weight = if f <= center {
+                            (f - low) / (center - low)
+                        } else {
+                            (high - f) / (high - center)
+                        };
+                        (weight > 0.0).then_some((k, weight))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            window,
+            cos,
+            sin,
+            filters,
+        }
+    }
+
+    /// The log-mel spectrum of every whole frame of `samples`, in dB, or of
+    /// one frame padded with silence if they are shorter than a frame.
+    fn spectra(&self, samples: &[f32]) -> Vec<Vec<f64>> {
+        let frames = match samples.len() {
+            0 => 0,
+            len => len.saturating_sub(FRAME_LEN) / FRAME_HOP + 1,
+        };
+        (0..frames)
+            .map(|i| i * FRAME_HOP)
+            .map(|start| {
+                let frame: Vec<f64> = (0..FRAME_LEN)
+                    .map(|n| samples.get(start + n).copied().unwrap_or(0.0) as f64 * self.window[n])
+                    .collect();
+                let power: Vec<f64> = self
+                    .cos
+                    .iter()
+                    .zip(&self.sin)
+                    .map(|(cos, sin)| {
+                        let re: f64 = frame.iter().zip(cos).map(|(x, c)| x * c).sum();
+                        let im: f64 = frame.iter().zip(sin).map(|(x, s)| x * s).sum();
+                        re * re + im * im
+                    })
+                    .collect();
+                self.filters
+                    .iter()
+                    .map(|filter| {
+                        let band: f64 = filter.iter().map(|&(k, weight)| power[k] * weight).sum();
+                        10.0 * band.max(POWER_FLOOR).log10()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Formats results as a table, one row per configuration.
+pub fn format_results(results: &[BenchResult]) -> String {
+    let with_quality = results.iter().any(|r| r.quality.is_some());
+    let mut table = format!(
+        "{:<16} {:>8} {:>10} {:>10} {:>14}",
+        "config", "requests", "time (s)", "req/s", "audio s/s"
+    );
+    if with_quality {
+        table.push_str(&format!(" {:>10} {:>8}", "dist (dB)", "length"));
+    }
+    table.push('\n');
+    for result in results {
+        table.push_str(&format!(
+            "{:<16} {:>8} {:>10.2} {:>10.2} {:>14.2}",
+            result.label,
+            result.requests,
+            result.elapsed,
+            result.requests_per_sec(),
+            result.audio_per_sec()
+        ));
+        if with_quality {
+            match result.quality {
+                Some(quality) => table.push_str(&format!(
+                    " {:>10.2} {:>8}",
+                    quality.distance_db,
+                    format!("{:+.1}%", quality.length_change * 100.0)
+                )),
+                None => table.push_str(&format!(" {:>10} {:>8}", "-", "-")),
+            }
+        }
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_results() {
+        let results = [BenchResult {
+            label: "2 sessions".to_string(),
+            requests: 8,
+            elapsed: 4.0,
+            audio_seconds: 40.0,
+            quality: None,
+        }];
+        assert_eq!(results[0].requests_per_sec(), 2.0);
+        assert_eq!(results[0].audio_per_sec(), 10.0);
+
+        let table = format_results(&results);
+        let row = table.lines().nth(1).unwrap();
+        assert!(row.starts_with("2 sessions"));
+        assert!(row.ends_with("10.00"));
+    }
+
+    #[test]
+    fn test_format_results_with_quality() {
+        let result = |label: &str, quality| BenchResult {
+            label: label.to_string(),
+            requests: 4,
+            elapsed: 2.0,
+            audio_seconds: 8.0,
+            quality,
+        };
+        let q4 = Quality {
+            distance_db: 1.254,
+            length_change: -0.031,
+        };
+        let table = format_results(&[result("fp32", None), result("q4", Some(q4))]);
+        assert!(table.lines().next().unwrap().ends_with("dist (dB)   length"));
+        assert!(table.lines().nth(1).unwrap().ends_with("-        -"));
+        assert!(table.lines().nth(2).unwrap().ends_with("1.25    -3.1%"));
+    }
+
+    /// `len` samples of a sine of `hz` at 24 kHz, after `delay` samples of silence.
+    fn tone(hz: f32, len: usize, delay: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; delay];
+        samples.extend((0..len).map(|i| (i as f32 * hz * 2.0 * std::f32::consts::PI / 24000.0).sin() * 0.5));
+        samples
+    }
+
+    #[test]
+    fn test_spectral_distance() {
+        let reference = tone(440.0, 24000, 0);
+        assert_eq!(spectral_distance(&reference, &reference, 24000), 0.0);
+
+        // A tenth of the amplitude off is under 1 dB in every band
+        let scaled: Vec<f32> = reference.iter().map(|s| s * 0.9).collect();
+        let distance = spectral_distance(&reference, &scaled, 24000);
+        assert!(distance > 0.1 && distance < 0.92, "{}", distance);
+
+        assert!(spectral_distance(&reference, &tone(880.0, 24000, 0), 24000) > 10.0);
+        assert_eq!(spectral_distance(&reference, &[], 24000), f32::INFINITY);
+    }
+
+    #[test]
+    fn test_shifted_audio_is_not_different() {
+        let reference = tone(440.0, 24000, 0);
+        let shifted = tone(440.0, 30000, 100);
+        let quality = compare(&reference, &shifted, 24000);
+        assert!(quality.distance_db < 1.0, "{}", quality.distance_db);
+        assert!((quality.length_change - 0.254).abs() < 1e-3);
+    }
+}
@@ -1,17 +1,39 @@
+mod bench;
 mod onn;
 mod serve;
 mod tts;
 mod utils;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use futures::StreamExt;
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use tts::koko::TTSKoko;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use onn::config::{ExecutionMode, OptimizationLevel};
+use onn::{OrtConfig, Provider};
+use serve::pool::PoolConfig;
+use tts::audio::AudioBuffer;
+use tts::chunk::ChunkConfig;
+use tts::encode::AudioFormat;
+use tts::koko::{TTSKoko, TTSOpts};
+use tts::precision::Precision;
+use utils::fileio::part_path;
+use utils::models::{find_file, ModelFile, ModelStore, Verification, CATALOG, KOKORO_FP32, VOICES};
+use tts::stream::ChunkCollector;
+use tts::subtitles::{cues, SubtitleFormat};
+use tts::timestamps::WordTiming;
+use tts::tokenize::UnknownPolicy;
 
 #[derive(Parser, Debug)]
 #[command(name = "kokoros")]
 #[command(version = "0.1")]
 #[command(author = "Lucas Jin")]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(short = 't', long = "text", value_name = "TEXT")]
     text: Option<String>,
 
@@ -19,21 +41,283 @@ struct Cli {
         short = 'l',
         long = "lan",
         value_name = "LANGUAGE",
-        help = "https://github.com/espeak-ng/espeak-ng/blob/master/docs/languages.md"
+        help = "Kokoro language code (a, b, e, f, h, i, j, p, z) or espeak-ng language, see https://github.com/espeak-ng/espeak-ng/blob/master/docs/languages.md"
     )]
     lan: Option<String>,
 
     #[arg(short = 'm', long = "model", value_name = "MODEL")]
     model: Option<String>,
 
+    #[arg(
+        long = "precision",
+        value_name = "PRECISION",
+        default_value = "fp32",
+        help = "Model weights to use: fp32, fp16, int8 or q4, downloaded if missing"
+    )]
+    precision: Precision,
+
+    #[arg(
+        long = "voices",
+        value_name = "PATH",
+        help = "Voice file (.pt, .npy, .npz, .bin or .json) or directory of them, instead of data/ or the downloaded voices"
+    )]
+    voices: Option<PathBuf>,
+
+    #[arg(
+        long = "mirror",
+        value_name = "URL",
+        help = "Download models from this mirror of huggingface.co instead, also read from KOKO_MIRROR. Voices still come from GitHub"
+    )]
+    mirror: Option<String>,
+
+    #[arg(long = "offline", help = "Never download, fail if a model file is missing")]
+    offline: bool,
+
     #[arg(short = 's', long = "style", value_name = "STYLE")]
     style: Option<String>,
 
+    #[arg(
+        short = 'o',
+        long = "output",
+        value_name = "OUTPUT",
+        default_value = "tmp/output.wav",
+        help = "Path of the audio file to write"
+    )]
+    output: String,
+
+    #[arg(
+        short = 'f',
+        long = "format",
+        value_name = "FORMAT",
+        help = "Audio format: wav, pcm, flac, mp3 or opus (ogg), guessed from the --output extension when omitted"
+    )]
+    format: Option<AudioFormat>,
+
+    #[arg(
+        long = "speed",
+        value_name = "SPEED",
+        default_value_t = 1.0,
+        help = "Speaking rate, from 0.25 (slower) to 4.0 (faster)"
+    )]
+    speed: f32,
+
+    #[arg(
+        long = "no-normalize",
+        help = "Pass English text to the phonemizer as is, without normalizing numbers, titles and acronyms"
+    )]
+    no_normalize: bool,
+
+    #[arg(
+        long = "unknown-chars",
+        value_name = "POLICY",
+        default_value = "drop",
+        help = "What to do with phonemes the vocab cannot tokenize: drop, substitute or error"
+    )]
+    unknown_chars: UnknownPolicy,
+
+    #[arg(
+        long = "substitutions",
+        value_name = "FILE",
+        help = "JSON object of extra phoneme replacements for --unknown-chars substitute, e.g. {\"ʦ\": \"ts\"}"
+    )]
+    substitutions: Option<String>,
+
+    #[arg(
+        long = "silence-ms",
+        value_name = "MS",
+        default_value_t = 100,
+        help = "Silence inserted between sentences, in milliseconds"
+    )]
+    silence_ms: u32,
+
+    #[arg(
+        long = "crossfade-ms",
+        value_name = "MS",
+        default_value_t = 10,
+        help = "Crossfade applied where audio chunks are joined, in milliseconds"
+    )]
+    crossfade_ms: u32,
+
+    #[arg(
+        long = "timestamps",
+        value_name = "FILE",
+        help = "Write word and phoneme start/end times as JSON, needs a model with a duration output"
+    )]
+    timestamps: Option<String>,
+
+    #[arg(
+        long = "subtitles",
+        value_name = "FILE",
+        help = "Write captions timed to the audio, as SRT or WebVTT depending on the .srt or .vtt extension"
+    )]
+    subtitles: Option<String>,
+
+    #[arg(
+        long = "stream",
+        help = "Synthesize sentence by sentence, reporting when each audio chunk is ready"
+    )]
+    stream: bool,
+
     #[arg(long = "oai", value_name = "OpenAI server")]
     oai: bool,
 
-    #[arg(long = "gpu", help = "Enable GPU acceleration")]
+    #[arg(
+        long = "workers",
+        value_name = "N",
+        default_value_t = 2,
+        help = "Requests the server synthesizes at the same time"
+    )]
+    workers: usize,
+
+    #[arg(
+        long = "queue-size",
+        value_name = "N",
+        default_value_t = 16,
+        help = "Requests the server queues while all workers are busy, further ones get a 503"
+    )]
+    queue_size: usize,
+
+    #[arg(
+        long = "request-timeout",
+        value_name = "SECS",
+        default_value_t = 120,
+        help = "Longest the server spends on a request, time in the queue included"
+    )]
+    request_timeout: u64,
+
+    #[arg(long = "gpu", help = "Enable GPU acceleration, same as --provider cuda:0")]
     gpu: bool,
+
+    #[arg(
+        long = "provider",
+        value_name = "LIST",
+        value_delimiter = ',',
+        help = "Execution providers to try in order, comma separated: cpu, cuda[:ID], tensorrt[:ID], openvino[:DEVICE], coreml, directml[:ID], xnnpack [default: cpu]"
+    )]
+    providers: Vec<Provider>,
+
+    #[arg(
+        long = "sessions",
+        value_name = "N",
+        help = "Model sessions to load, each one runs an inference at a time and holds a copy of the weights [default: 1]"
+    )]
+    sessions: Option<usize>,
+
+    #[arg(
+        long = "ort-config",
+        value_name = "FILE",
+        help = "JSON file of ONNX Runtime session settings, the flags below override it"
+    )]
+    ort_config: Option<String>,
+
+    #[arg(
+        long = "intra-threads",
+        value_name = "N",
+        help = "Threads used within each operator, 0 for one per physical core [default: 1]"
+    )]
+    intra_threads: Option<usize>,
+
+    #[arg(
+        long = "inter-threads",
+        value_name = "N",
+        help = "Threads running independent operators in parallel execution mode, 0 for the ONNX Runtime default [default: 0]"
+    )]
+    inter_threads: Option<usize>,
+
+    #[arg(
+        long = "execution-mode",
+        value_name = "MODE",
+        help = "Operator scheduling: sequential or parallel [default: sequential]"
+    )]
+    execution_mode: Option<ExecutionMode>,
+
+    #[arg(
+        long = "optimization-level",
+        value_name = "LEVEL",
+        help = "Graph optimizations: disable, basic, extended or all [default: all]"
+    )]
+    optimization_level: Option<OptimizationLevel>,
+
+    #[arg(
+        long = "mem-pattern",
+        value_name = "BOOL",
+        help = "Reuse the memory plan of previous runs [default: true]"
+    )]
+    mem_pattern: Option<bool>,
+
+    #[arg(
+        long = "cpu-arena",
+        value_name = "BOOL",
+        help = "Pool CPU allocations in an arena [default: true]"
+    )]
+    cpu_arena: Option<bool>,
+
+    #[arg(
+        long = "save-optimized-model",
+        value_name = "FILE",
+        help = "Write the model as optimized by ONNX Runtime to FILE"
+    )]
+    save_optimized_model: Option<String>,
+
+    #[arg(
+        long = "bench-sessions",
+        value_name = "N,N,...",
+        value_delimiter = ',',
+        help = "Instead of synthesizing, measure throughput with each of these session pool sizes"
+    )]
+    bench_sessions: Vec<usize>,
+
+    #[arg(
+        long = "bench-precision",
+        value_name = "P,P,...",
+        value_delimiter = ',',
+        help = "Instead of synthesizing, compare the speed and output of each of these precisions with fp32"
+    )]
+    bench_precision: Vec<Precision>,
+
+    #[arg(
+        long = "bench-requests",
+        value_name = "N",
+        default_value_t = 8,
+        help = "Requests synthesized per --bench-sessions pool size or --bench-precision"
+    )]
+    bench_requests: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage the model and voice files in the model directory, KOKO_HOME
+    /// or the user cache directory
+    Models {
+        #[command(subcommand)]
+        action: ModelsAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ModelsAction {
+    /// Show the known files and whether they are downloaded
+    List,
+    /// Download files, the fp32 model and the voices by default
+    Pull {
+        #[arg(value_name = "NAME")]
+        names: Vec<String>,
+    },
+    /// Check downloaded files against their checksums, all by default
+    Verify {
+        #[arg(value_name = "NAME")]
+        names: Vec<String>,
+    },
+    /// Delete downloaded files
+    Rm {
+        #[arg(value_name = "NAME", required = true)]
+        names: Vec<String>,
+    },
+    /// Print the model directory, or where the named file is kept
+    Path {
+        #[arg(value_name = "NAME")]
+        name: Option<String>,
+    },
 }
 
 // 定义一个线程安全的错误类型
@@ -48,19 +332,323 @@ impl std::fmt::Display for ThreadSafeError {
     }
 }
 
+/// Reads a `{"char": "replacement"}` JSON object of phoneme substitutions.
+fn load_substitutions(path: &str) -> Result<HashMap<char, String>, String> {
+    let value = utils::fileio::load_json_file(path)?;
+    let obj = value
+        .as_object()
+        .ok_or_else(|| format!("{}: expected a JSON object", path))?;
+
+    let mut table = HashMap::new();
+    for (key, value) in obj {
+        let mut chars = key.chars();
+        let (Some(ch), None) = (chars.next(), chars.next()) else {
+            return Err(format!("{}: key {:?} must be a single character", path, key));
+        };
+        let replacement = value
+            .as_str()
+            .ok_or_else(|| format!("{}: value for {:?} must be a string", path, key))?;
+        table.insert(ch, replacement.to_string());
+    }
+    Ok(table)
+}
+
+/// Session settings from `--ort-config`, or the defaults, with the flags
+/// applied on top.
+fn ort_config(args: &Cli) -> Result<OrtConfig, String> {
+    let mut config = match &args.ort_config {
+        Some(path) => OrtConfig::from_json_file(path)?,
+        None => TTSKoko::default_config(args.gpu),
+    };
+    if args.gpu {
+        config = config.with_gpu(true);
+    }
+    if !args.providers.is_empty() {
+        config = config.with_providers(args.providers.clone());
+    }
+    if let Some(sessions) = args.sessions {
+        config = config.with_sessions(sessions);
+    }
+    if let Some(threads) = args.intra_threads {
+        config = config.with_intra_threads(threads);
+    }
+    if let Some(threads) = args.inter_threads {
+        config = config.with_inter_threads(threads);
+    }
+    if let Some(mode) = args.execution_mode {
+        config = config.with_execution_mode(mode);
+    }
+    if let Some(level) = args.optimization_level {
+        config = config.with_optimization_level(level);
+    }
+    if let Some(enabled) = args.mem_pattern {
+        config = config.with_mem_pattern(enabled);
+    }
+    if let Some(enabled) = args.cpu_arena {
+        config = config.with_cpu_arena(enabled);
+    }
+    if args.save_optimized_model.is_some() {
+        config = config.with_optimized_model_path(args.save_optimized_model.clone());
+    }
+    Ok(config)
+}
+
+/// Where missing models come from, as set by `--mirror` and `--offline`.
+fn model_store(args: &Cli) -> ModelStore {
+    let mut store = ModelStore::new().with_offline(args.offline);
+    if let Some(mirror) = args.mirror.clone().or_else(|| std::env::var("KOKO_MIRROR").ok()) {
+        store = store.with_mirror(&mirror);
+    }
+    store
+}
+
+/// Catalog entries named on the command line, or `default` when none are.
+fn model_files(names: &[String], default: &[&'static ModelFile]) -> Result<Vec<&'static ModelFile>, String> {
+    if names.is_empty() {
+        return Ok(default.to_vec());
+    }
+    names.iter().map(|name| find_file(name)).collect()
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes >= 1 << 20 {
+        format!("{:.1} MB", bytes as f64 / (1 << 20) as f64)
+    } else {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    }
+}
+
+/// Runs `koko models ...`.
+fn models_command(action: &ModelsAction, store: &ModelStore) -> Result<(), String> {
+    match action {
+        ModelsAction::List => {
+            println!("Model directory: {}\n", store.dir().display());
+            println!("{:<8} {:<10} {:<32} path", "name", "size", "description");
+            for file in &CATALOG {
+                let path = store.path_for(file);
+                let size = match std::fs::metadata(&path) {
+                    Ok(meta) => format_size(meta.len()),
+                    Err(_) if part_path(&path).exists() => "partial".to_string(),
+                    Err(_) => "-".to_string(),
+                };
+                println!("{:<8} {:<10} {:<32} {}", file.name, size, file.description, path.display());
+            }
+        }
+        ModelsAction::Pull { names } => {
+            for file in model_files(names, &[&KOKORO_FP32, &VOICES])? {
+                let path = store.stored_path(file);
+                if path.exists() {
+                    println!("{} is already at {}", file.name, path.display());
+                    continue;
+                }
+                store.ensure(file, &path)?;
+                println!("✓ Pulled {} to {}", file.name, path.display());
+            }
+        }
+        ModelsAction::Verify { names } => {
+            let all: Vec<&ModelFile> = CATALOG.iter().collect();
+            let mut failed = Vec::new();
+            for file in model_files(names, &all)? {
+                let path = store.path_for(file);
+                match store.verify(file, &path)? {
+                    Verification::Valid => println!("✓ {} ok", file.name),
+                    Verification::Unrecorded { sha256 } => {
+                        println!("? {} has no checksum in the manifest, sha256 {}", file.name, sha256)
+                    }
+                    Verification::Mismatch { expected, actual } => {
+                        println!("✗ {} is corrupt: expected sha256 {}, got {}", file.name, expected, actual);
+                        failed.push(file.name);
+                    }
+                    // Only files asked for by name are reported missing
+                    Verification::Missing if names.is_empty() => {}
+                    Verification::Missing => {
+                        println!("✗ {} is not downloaded", file.name);
+                        failed.push(file.name);
+                    }
+                }
+            }
+            if !failed.is_empty() {
+                return Err(format!(
+                    "verification failed for {}, run `koko models rm` and `koko models pull` on them",
+                    failed.join(", ")
+                ));
+            }
+        }
+        ModelsAction::Rm { names } => {
+            for file in model_files(names, &[])? {
+                let path = store.stored_path(file);
+                if store.remove(&path)? {
+                    println!("Deleted {}", path.display());
+                } else {
+                    println!("{} is not downloaded", file.name);
+                }
+                // Files at the older locations were put there by hand, so
+                // they are left alone
+                if let Some(legacy) = store.legacy_path(file) {
+                    println!("{} at {} is outside the model directory, delete it yourself", file.name, legacy.display());
+                }
+            }
+        }
+        ModelsAction::Path { name } => match name {
+            Some(name) => println!("{}", store.path_for(find_file(name)?).display()),
+            None => println!("{}", store.dir().display()),
+        },
+    }
+    Ok(())
+}
+
+/// Loads the model, downloading it first if needed. The download blocks,
+/// so it runs outside of the async context.
+fn load_tts(
+    model_path: &str,
+    voices_path: Option<&Path>,
+    precision: Precision,
+    config: OrtConfig,
+    store: &ModelStore,
+) -> Result<TTSKoko, ThreadSafeError> {
+    tokio::task::block_in_place(|| TTSKoko::load(model_path, voices_path, precision, config, store))
+        .map_err(ThreadSafeError)
+}
+
+/// Writes word timings as pretty-printed JSON, creating parent directories.
+fn save_timestamps(path: &str, words: &[WordTiming]) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(words)?)?;
+    Ok(())
+}
+
+/// Writes the captions of `audio` to `path`, creating parent directories.
+fn save_subtitles(path: &str, format: SubtitleFormat, audio: &AudioBuffer) -> Result<usize, Box<dyn std::error::Error>> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let cues = cues(audio);
+    std::fs::write(path, format.render(&cues))?;
+    Ok(cues.len())
+}
+
+/// Synthesizes through the streaming API, reporting each chunk as it arrives,
+/// and collects the chunks into one buffer.
+async fn stream_tts(
+    tts: Arc<TTSKoko>,
+    txt: &str,
+    lan: &str,
+    style: &str,
+    opts: TTSOpts,
+) -> Result<AudioBuffer, ThreadSafeError> {
+    let start = Instant::now();
+    let mut stream = tts
+        .tts_stream_async(txt, lan, style, opts)
+        .map_err(|e| ThreadSafeError(e.to_string()))?;
+
+    let mut collector = ChunkCollector::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| ThreadSafeError(e.to_string()))?;
+        println!(
+            "Chunk {} ready after {:.2?} ({:.2}s of audio): {}",
+            chunk.index,
+            chunk.latency,
+            chunk.samples.len() as f32 / chunk.sample_rate as f32,
+            chunk.phonemes
+        );
+        collector.push(chunk);
+    }
+
+    Ok(collector.finish(start.elapsed().as_secs_f32()))
+}
+
 fn run_app() -> Result<(), ThreadSafeError> {
     let rt = tokio::runtime::Runtime::new().map_err(|e| ThreadSafeError(e.to_string()))?;
     rt.block_on(async {
         let args = Cli::parse();
+        let store = model_store(&args);
+        // Managing the model files loads no model, so bad ONNX Runtime
+        // options do not get in the way
+        if let Some(Command::Models { action }) = &args.command {
+            return tokio::task::block_in_place(|| models_command(action, &store)).map_err(ThreadSafeError);
+        }
+        let ort_config = ort_config(&args).map_err(ThreadSafeError)?;
 
-        let model_path = args.model.unwrap_or_else(|| "checkpoints/kokoro-v0_19.onnx".to_string());
+        let model_path = args
+            .model
+            .unwrap_or_else(|| store.path_for(args.precision.model_file()).to_string_lossy().into_owned());
         let style = args.style.unwrap_or_else(|| "af_sarah.4+af_nicole.6".to_string());
         let lan = args.lan.unwrap_or_else(|| "en-us".to_string());
+        TTSKoko::validate_speed(args.speed).map_err(ThreadSafeError)?;
+
+        let chunk_config = ChunkConfig::new()
+            .with_sentence_silence_ms(args.silence_ms)
+            .with_crossfade_ms(args.crossfade_ms);
+        if !args.bench_sessions.is_empty() {
+            let opts = TTSOpts::new().with_speed(args.speed);
+            let txt = args.text.as_deref().unwrap_or(bench::BENCH_TEXT);
+            let mut results = Vec::new();
+            for &sessions in &args.bench_sessions {
+                let config = ort_config.clone().with_sessions(sessions);
+                let tts = load_tts(&model_path, args.voices.as_deref(), args.precision, config, &store)?
+                    .with_chunk_config(chunk_config.clone());
+                let label = format!("{} sessions", sessions);
+                results.push(
+                    bench::run_throughput(&label, &tts, txt, &lan, &style, &opts, args.bench_requests, sessions)
+                        .map_err(ThreadSafeError)?,
+                );
+            }
+            println!("\n{}", bench::format_results(&results));
+            return Ok(());
+        }
+        if !args.bench_precision.is_empty() {
+            let opts = TTSOpts::new().with_speed(args.speed);
+            let txt = args.text.as_deref().unwrap_or(bench::BENCH_TEXT);
+            // fp32 always runs first, as the reference
+            let mut precisions = vec![Precision::Fp32];
+            precisions.extend(args.bench_precision.iter().filter(|p| **p != Precision::Fp32));
+
+            let mut reference: Option<Vec<f32>> = None;
+            let mut results = Vec::new();
+            for precision in precisions {
+                let path = store.path_for(precision.model_file());
+                let tts = load_tts(&path.to_string_lossy(), args.voices.as_deref(), precision, ort_config.clone(), &store)?
+                    .with_chunk_config(chunk_config.clone());
+                let mut result = bench::run_throughput(
+                    precision.as_str(),
+                    &tts,
+                    txt,
+                    &lan,
+                    &style,
+                    &opts,
+                    args.bench_requests,
+                    tts.pool_size(),
+                )
+                .map_err(ThreadSafeError)?;
+                let audio = tts
+                    .tts(txt, &lan, &style, &opts)
+                    .map_err(|e| ThreadSafeError(e.to_string()))?;
+                let reference = reference.get_or_insert_with(|| audio.samples.clone());
+                result.quality = Some(bench::compare(reference, &audio.samples, TTSKoko::SAMPLE_RATE));
+                results.push(result);
+            }
+            println!("\n{}", bench::format_results(&results));
+            return Ok(());
+        }
 
-        let tts = TTSKoko::with_gpu(&model_path, args.gpu);
+        let tts = load_tts(&model_path, args.voices.as_deref(), args.precision, ort_config, &store)?.with_chunk_config(chunk_config);
 
         if args.oai {
-            let app = serve::openai::create_server(tts).await;
+            if args.workers > tts.pool_size() {
+                println!(
+                    "Note: {} workers share {} model sessions, use --sessions {} to run them all in parallel",
+                    args.workers,
+                    tts.pool_size(),
+                    args.workers
+                );
+            }
+            let pool_config = PoolConfig::new()
+                .with_workers(args.workers)
+                .with_queue_size(args.queue_size)
+                .with_timeout(Duration::from_secs(args.request_timeout));
+            let app = serve::openai::create_server(tts, pool_config).await;
             let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
             println!("Starting OpenAI-compatible server on http://localhost:3000");
             axum::serve(
@@ -79,7 +667,68 @@ This is one of the top notch Rust based inference models, and I'm sure you'll lo
                 "#
                 .to_string()
             });
-            tts.tts(&txt, &lan, &style).map_err(|e| ThreadSafeError(e.to_string()))?;
+            let mut unknown_policy = args.unknown_chars;
+            if let Some(path) = &args.substitutions {
+                let UnknownPolicy::Substitute(table) = &mut unknown_policy else {
+                    return Err(ThreadSafeError(
+                        "--substitutions requires --unknown-chars substitute".to_string(),
+                    ));
+                };
+                table.extend(load_substitutions(path).map_err(ThreadSafeError)?);
+            }
+
+            let format = args
+                .format
+                .or_else(|| AudioFormat::from_extension(&args.output))
+                .unwrap_or_default();
+
+            if args.stream && args.timestamps.is_some() {
+                return Err(ThreadSafeError(
+                    "--timestamps cannot be combined with --stream".to_string(),
+                ));
+            }
+
+            let subtitles = match &args.subtitles {
+                Some(path) => Some((
+                    path,
+                    SubtitleFormat::from_extension(path).ok_or_else(|| {
+                        ThreadSafeError(format!("{}: --subtitles must end in .srt or .vtt", path))
+                    })?,
+                )),
+                None => None,
+            };
+
+            // Captions are only cut at word times when `--timestamps` asked
+            // for them, they never change how the audio is synthesized
+            let opts = TTSOpts::new()
+                .with_speed(args.speed)
+                .with_normalize(!args.no_normalize)
+                .with_unknown_policy(unknown_policy)
+                .with_timestamps(args.timestamps.is_some());
+            let audio = if args.stream {
+                stream_tts(Arc::new(tts), &txt, &lan, &style, opts).await?
+            } else {
+                tts.tts(&txt, &lan, &style, &opts)
+                    .map_err(|e| ThreadSafeError(e.to_string()))?
+            };
+            audio
+                .save(&args.output, format)
+                .map_err(|e| ThreadSafeError(e.to_string()))?;
+            if let Some(path) = &args.timestamps {
+                save_timestamps(path, &audio.words).map_err(|e| ThreadSafeError(e.to_string()))?;
+                println!("Timestamps for {} words saved to {}", audio.words.len(), path);
+            }
+            if let Some((path, subtitle_format)) = subtitles {
+                let count = save_subtitles(path, subtitle_format, &audio).map_err(|e| ThreadSafeError(e.to_string()))?;
+                println!("{} captions saved to {}", count, path);
+            }
+            for unknown in &audio.unknown {
+                eprintln!("warning: unsupported phoneme {}", unknown);
+            }
+            println!(
+                "Audio saved to {} ({}, {:.2}s, {} phonemes, RTF {:.3})",
+                args.output, format, audio.duration, audio.phonemes_len, audio.real_time_factor
+            );
             Ok(())
         }
     })
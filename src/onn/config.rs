@@ -1,18 +1,203 @@
 use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+use ort::session::builder::GraphOptimizationLevel;
+use serde::Deserialize;
+
+/// An ONNX Runtime execution provider, written `name` or `name:device`,
+/// e.g. `cuda:1` or `openvino:GPU`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Provider {
+    Cpu,
+    Cuda { device_id: i32 },
+    TensorRT { device_id: i32 },
+    /// OpenVINO on a device type such as `CPU`, `GPU` or `NPU`, OpenVINO
+    /// picks one when unset.
+    OpenVINO { device_type: Option<String> },
+    CoreML,
+    DirectML { device_id: i32 },
+    Xnnpack,
+}
+
+const PROVIDER_NAMES: [&str; 7] = ["cpu", "cuda", "tensorrt", "openvino", "coreml", "directml", "xnnpack"];
+
+impl FromStr for Provider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, device) = match s.split_once(':') {
+            Some((name, device)) => (name, Some(device)),
+            None => (s, None),
+        };
+        let device_id = || -> Result<i32, String> {
+            device.map_or(Ok(0), |d| {
+                d.parse()
+                    .map_err(|_| format!("invalid device id '{}' for provider {}", d, name))
+            })
+        };
+        let no_device = |provider: Provider| match device {
+            Some(_) => Err(format!("provider {} does not take a device", name)),
+            None => Ok(provider),
+        };
+
+        match name.to_ascii_lowercase().as_str() {
+            "cpu" => no_device(Provider::Cpu),
+            "cuda" => Ok(Provider::Cuda { device_id: device_id()? }),
+            "tensorrt" => Ok(Provider::TensorRT { device_id: device_id()? }),
+            "openvino" => Ok(Provider::OpenVINO {
+                device_type: device.map(str::to_string),
+            }),
+            "coreml" => no_device(Provider::CoreML),
+            "directml" => Ok(Provider::DirectML { device_id: device_id()? }),
+            "xnnpack" => no_device(Provider::Xnnpack),
+            other => Err(format!(
+                "unknown execution provider '{}', expected one of: {}",
+                other,
+                PROVIDER_NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Provider {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Provider::Cpu => f.write_str("cpu"),
+            Provider::Cuda { device_id } => write!(f, "cuda:{}", device_id),
+            Provider::TensorRT { device_id } => write!(f, "tensorrt:{}", device_id),
+            Provider::OpenVINO { device_type: None } => f.write_str("openvino"),
+            Provider::OpenVINO { device_type: Some(device) } => write!(f, "openvino:{}", device),
+            Provider::CoreML => f.write_str("coreml"),
+            Provider::DirectML { device_id } => write!(f, "directml:{}", device_id),
+            Provider::Xnnpack => f.write_str("xnnpack"),
+        }
+    }
+}
+
+/// How the operators of a graph are scheduled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutionMode {
+    /// One operator at a time, each using the intra-op threads.
+    #[default]
+    Sequential,
+    /// Independent branches of the graph run concurrently on the inter-op
+    /// threads.
+    Parallel,
+}
+
+impl FromStr for ExecutionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sequential" => Ok(ExecutionMode::Sequential),
+            "parallel" => Ok(ExecutionMode::Parallel),
+            other => Err(format!(
+                "unknown execution mode '{}', expected sequential or parallel",
+                other
+            )),
+        }
+    }
+}
+
+/// Graph optimizations applied when the session is created, named as in
+/// the ONNX Runtime documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptimizationLevel {
+    Disable,
+    Basic,
+    Extended,
+    #[default]
+    All,
+}
+
+impl OptimizationLevel {
+    pub fn to_ort(self) -> GraphOptimizationLevel {
+        match self {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Basic => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Extended => GraphOptimizationLevel::Level2,
+            OptimizationLevel::All => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+impl FromStr for OptimizationLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "disable" | "0" => Ok(OptimizationLevel::Disable),
+            "basic" | "1" => Ok(OptimizationLevel::Basic),
+            "extended" | "2" => Ok(OptimizationLevel::Extended),
+            "all" | "3" => Ok(OptimizationLevel::All),
+            other => Err(format!(
+                "unknown optimization level '{}', expected disable, basic, extended or all",
+                other
+            )),
+        }
+    }
+}
+
+/// Session settings, read from a JSON file with `from_json_file` where every
+/// field is optional, e.g. `{"intra_threads": 4, "execution_mode": "parallel"}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct OrtConfig {
-    pub use_gpu: bool,
+    /// Execution providers to try, in order of preference. Every one that
+    /// can be registered is, nodes going to the first that supports them.
+    pub providers: Vec<Provider>,
+    /// Memory limit of the CUDA provider, in bytes.
     pub gpu_memory_limit: Option<usize>,
+    /// Run on the CPU when none of `providers` can be registered.
     pub fallback_to_cpu: bool,
+    /// Sessions loaded from the model, each running one inference at a
+    /// time, so up to this many requests are synthesized in parallel.
+    pub sessions: usize,
+    /// Threads used within an operator, 0 lets ONNX Runtime use one per
+    /// physical core.
+    pub intra_threads: usize,
+    /// Threads running independent operators with `ExecutionMode::Parallel`,
+    /// 0 lets ONNX Runtime decide.
+    pub inter_threads: usize,
+    pub execution_mode: ExecutionMode,
+    pub optimization_level: OptimizationLevel,
+    /// Plan memory from the first run and reuse it, which pays off when the
+    /// input shapes repeat.
+    pub mem_pattern: bool,
+    /// Pool CPU allocations in an arena instead of going to the system
+    /// allocator every time.
+    pub cpu_arena: bool,
+    /// Where to write the model after graph optimizations, to inspect it or
+    /// load it later with optimizations disabled.
+    pub optimized_model_path: Option<String>,
 }
 
 impl Default for OrtConfig {
     fn default() -> Self {
         Self {
-            use_gpu: false,
+            providers: vec![Provider::Cpu],
             gpu_memory_limit: None,
             fallback_to_cpu: true,
+            sessions: 1,
+            intra_threads: 1,
+            inter_threads: 0,
+            execution_mode: ExecutionMode::default(),
+            optimization_level: OptimizationLevel::default(),
+            mem_pattern: true,
+            cpu_arena: true,
+            optimized_model_path: None,
         }
     }
 }
@@ -22,11 +207,32 @@ impl OrtConfig {
         Self::default()
     }
 
-    pub fn with_gpu(mut self, enabled: bool) -> Self {
-        self.use_gpu = enabled;
+    /// Reads a config from a JSON object, fields left out keep their
+    /// default.
+    pub fn from_json_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        serde_json::from_str(&content).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn with_providers(mut self, providers: Vec<Provider>) -> Self {
+        self.providers = providers;
         self
     }
 
+    /// Shorthand for CUDA on the first GPU, or CPU only.
+    pub fn with_gpu(self, enabled: bool) -> Self {
+        if enabled {
+            self.with_providers(vec![Provider::Cuda { device_id: 0 }])
+        } else {
+            self.with_providers(vec![Provider::Cpu])
+        }
+    }
+
+    /// Whether a provider other than the CPU is requested.
+    pub fn uses_accelerator(&self) -> bool {
+        self.providers.iter().any(|p| *p != Provider::Cpu)
+    }
+
     pub fn with_gpu_memory_limit(mut self, limit: Option<usize>) -> Self {
         self.gpu_memory_limit = limit;
         self
@@ -36,14 +242,130 @@ impl OrtConfig {
         self.fallback_to_cpu = fallback;
         self
     }
+
+    pub fn with_sessions(mut self, sessions: usize) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    pub fn with_intra_threads(mut self, threads: usize) -> Self {
+        self.intra_threads = threads;
+        self
+    }
+
+    pub fn with_inter_threads(mut self, threads: usize) -> Self {
+        self.inter_threads = threads;
+        self
+    }
+
+    pub fn with_execution_mode(mut self, mode: ExecutionMode) -> Self {
+        self.execution_mode = mode;
+        self
+    }
+
+    pub fn with_optimization_level(mut self, level: OptimizationLevel) -> Self {
+        self.optimization_level = level;
+        self
+    }
+
+    pub fn with_mem_pattern(mut self, enabled: bool) -> Self {
+        self.mem_pattern = enabled;
+        self
+    }
+
+    pub fn with_cpu_arena(mut self, enabled: bool) -> Self {
+        self.cpu_arena = enabled;
+        self
+    }
+
+    pub fn with_optimized_model_path(mut self, path: Option<String>) -> Self {
+        self.optimized_model_path = path;
+        self
+    }
 }
 
 impl fmt::Display for OrtConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "OrtConfig {{ use_gpu: {}, gpu_memory_limit: {:?}, fallback_to_cpu: {} }}",
-            self.use_gpu, self.gpu_memory_limit, self.fallback_to_cpu
+            "OrtConfig {{ providers: [{}], gpu_memory_limit: {:?}, fallback_to_cpu: {}, sessions: {}, \
+             intra_threads: {}, inter_threads: {}, execution_mode: {:?}, optimization_level: {:?}, \
+             mem_pattern: {}, cpu_arena: {}, optimized_model_path: {:?} }}",
+            self.providers.iter().map(Provider::to_string).collect::<Vec<_>>().join(", "),
+            self.gpu_memory_limit,
+            self.fallback_to_cpu,
+            self.sessions,
+            self.intra_threads,
+            self.inter_threads,
+            self.execution_mode,
+            self.optimization_level,
+            self.mem_pattern,
+            self.cpu_arena,
+            self.optimized_model_path
         )
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_json_keeps_defaults() {
+        let config: OrtConfig = serde_json::from_str(
+            r#"{"intra_threads": 8, "execution_mode": "parallel", "optimization_level": "basic", "cpu_arena": false}"#,
+        )
+        .unwrap();
+        assert_eq!(config.intra_threads, 8);
+        assert_eq!(config.execution_mode, ExecutionMode::Parallel);
+        assert_eq!(config.optimization_level, OptimizationLevel::Basic);
+        assert!(!config.cpu_arena);
+        assert!(config.mem_pattern);
+        assert_eq!(config.sessions, 1);
+    }
+
+    #[test]
+    fn test_unknown_json_field_is_rejected() {
+        let err = serde_json::from_str::<OrtConfig>(r#"{"intra_thread": 8}"#).unwrap_err();
+        assert!(err.to_string().contains("intra_thread"));
+    }
+
+    #[test]
+    fn test_parse_providers() {
+        assert_eq!("cpu".parse::<Provider>(), Ok(Provider::Cpu));
+        assert_eq!("CUDA".parse::<Provider>(), Ok(Provider::Cuda { device_id: 0 }));
+        assert_eq!("tensorrt:2".parse::<Provider>(), Ok(Provider::TensorRT { device_id: 2 }));
+        assert_eq!(
+            "openvino:GPU".parse::<Provider>(),
+            Ok(Provider::OpenVINO { device_type: Some("GPU".to_string()) })
+        );
+        assert!("cuda:x".parse::<Provider>().is_err());
+        assert!("xnnpack:1".parse::<Provider>().is_err());
+        assert!("rocm".parse::<Provider>().is_err());
+
+        for name in ["cpu", "cuda:1", "tensorrt:0", "openvino", "openvino:NPU", "coreml", "directml:3", "xnnpack"] {
+            assert_eq!(name.parse::<Provider>().unwrap().to_string(), name);
+        }
+    }
+
+    #[test]
+    fn test_providers_from_json() {
+        let config: OrtConfig = serde_json::from_str(r#"{"providers": ["tensorrt:1", "cuda:1", "cpu"]}"#).unwrap();
+        assert_eq!(
+            config.providers,
+            vec![Provider::TensorRT { device_id: 1 }, Provider::Cuda { device_id: 1 }, Provider::Cpu]
+        );
+        assert!(config.uses_accelerator());
+        assert!(!OrtConfig::default().uses_accelerator());
+        assert!(serde_json::from_str::<OrtConfig>(r#"{"providers": ["gpu"]}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_levels_and_modes() {
+        assert_eq!("ALL".parse::<OptimizationLevel>(), Ok(OptimizationLevel::All));
+        assert_eq!("0".parse::<OptimizationLevel>(), Ok(OptimizationLevel::Disable));
+        assert!("max".parse::<OptimizationLevel>().is_err());
+        assert_eq!("parallel".parse::<ExecutionMode>(), Ok(ExecutionMode::Parallel));
+        assert!("async".parse::<ExecutionMode>().is_err());
+    }
+}
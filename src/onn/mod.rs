@@ -1,7 +1,8 @@
 pub mod ort_base;
 pub mod ort_koko;
 pub mod config;
+pub mod variant;
 
 pub use self::ort_koko::OrtKoko;
-pub use self::config::OrtConfig;
+pub use self::config::{OrtConfig, Provider};
 pub use self::ort_base::OrtBase;
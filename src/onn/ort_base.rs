@@ -1,67 +1,110 @@
-use ort::session::builder::{SessionBuilder, GraphOptimizationLevel};
+use ort::session::builder::SessionBuilder;
 use ort::session::Session;
-use ort::execution_providers::CUDAExecutionProvider;
+use ort::execution_providers::{
+    cuda::CUDAExecutionProviderCuDNNConvAlgoSearch, CPUExecutionProvider, CUDAExecutionProvider,
+    CoreMLExecutionProvider, DirectMLExecutionProvider, ExecutionProviderDispatch,
+    OpenVINOExecutionProvider, TensorRTExecutionProvider, XNNPACKExecutionProvider,
+};
 use ort::environment::Environment;
 
-use super::config::OrtConfig;
+use super::config::{ExecutionMode, OrtConfig, Provider};
 
 pub trait OrtBase {
     fn load_model(&mut self, model_path: String) -> Result<(), String> {
-        self.load_model_with_config(model_path, OrtConfig::default())
+        self.load_model_with_config(model_path, OrtConfig::default()).map(|_| ())
     }
 
-    fn load_model_with_config(&mut self, model_path: String, config: OrtConfig) -> Result<(), String> {
-        // 设置 CUDA 环境变量
-        if config.use_gpu {
-            println!("Setting up CUDA environment variables...");
-            std::env::set_var("CUDA_VISIBLE_DEVICES", "0");  // 强制使用第一个 NVIDIA GPU
-            std::env::set_var("CUDA_MODULE_LOADING", "LAZY");
-            std::env::set_var("ORT_CUDA_PROVIDER_OPTIONS", "cudnn_conv_algo_search=DEFAULT");
-            if let Some(limit) = config.gpu_memory_limit {
-                std::env::set_var("CUDA_MEMORY_LIMIT", limit.to_string());
-                println!("Set CUDA memory limit to {} GB", limit / (1024 * 1024 * 1024));
-            }
-        }
+    /// Loads the model as set by `config`, returning the execution provider
+    /// it runs on.
+    fn load_model_with_config(&mut self, model_path: String, config: OrtConfig) -> Result<Provider, String> {
+        let (session, provider) = self.build_session(&model_path, &config)?;
+        self.set_sess(session);
+        Ok(provider)
+    }
 
+    /// Creates a session for `model_path` as set by `config`, without
+    /// keeping it. Also returns the first of `config.providers` that could
+    /// be registered, which runs every node it supports.
+    fn build_session(&self, model_path: &str, config: &OrtConfig) -> Result<(Session, Provider), String> {
         let mut builder = SessionBuilder::new()
             .map_err(|e| format!("Failed to create session builder: {}", e))?;
 
         // 设置优化级别
         builder = builder
-            .with_optimization_level(GraphOptimizationLevel::Level3)
+            .with_optimization_level(config.optimization_level.to_ort())
             .map_err(|e| format!("Failed to set optimization level: {}", e))?;
 
         // 设置线程数
         builder = builder
-            .with_intra_threads(1)
+            .with_intra_threads(config.intra_threads)
+            .and_then(|b| b.with_inter_threads(config.inter_threads))
+            .and_then(|b| b.with_parallel_execution(config.execution_mode == ExecutionMode::Parallel))
             .map_err(|e| format!("Failed to set thread count: {}", e))?;
 
-        if config.use_gpu {
-            println!("Attempting to enable CUDA for NVIDIA GPU...");
-            
-            // 配置 CUDA 提供商
-            let cuda_provider = CUDAExecutionProvider::default()
-                .with_device_id(0)  // 使用第一个 NVIDIA GPU
-                .build();
-
-            // 尝试启用 CUDA
-            let cuda_result = builder.clone().with_execution_providers([cuda_provider]);
-            builder = if let Ok(b) = cuda_result {
-                println!("✓ CUDA execution provider successfully enabled");
-                println!("✓ GPU Device ID: 0");
-                println!("✓ Optimization Level: Level3");
-                println!("✓ Memory Limit: {} GB", config.gpu_memory_limit.unwrap_or(0) / (1024 * 1024 * 1024));
-                b
-            } else if config.fallback_to_cpu {
-                println!("⚠ Failed to enable CUDA for NVIDIA GPU, falling back to CPU");
-                println!("Please check:");
-                println!("1. If NVIDIA CUDA Toolkit is properly installed");
-                println!("2. Run 'nvidia-smi' to verify GPU is detected");
-                println!("3. Check if CUDA version matches the ONNX Runtime version");
-                builder
-            } else {
-                return Err("Failed to enable CUDA and fallback is disabled".to_string());
-            };
+        builder = builder
+            .with_memory_pattern(config.mem_pattern)
+            .map_err(|e| format!("Failed to set memory pattern: {}", e))?;
+
+        if let Some(path) = &config.optimized_model_path {
+            builder = builder
+                .with_optimized_model_path(path)
+                .map_err(|e| format!("Failed to set optimized model path: {}", e))?;
+            println!("Optimized model will be saved to {}", path);
+        }
+
+        println!(
+            "Session options: {} intra-op threads, {} inter-op threads, {:?} execution, {:?} optimizations, mem pattern {}, CPU arena {}",
+            config.intra_threads,
+            config.inter_threads,
+            config.execution_mode,
+            config.optimization_level,
+            config.mem_pattern,
+            config.cpu_arena
+        );
+
+        // Providers are registered one at a time so that each failure is
+        // reported, ONNX Runtime would otherwise skip them silently
+        let mut registered: Vec<Provider> = Vec::new();
+        for provider in &config.providers {
+            match builder.clone().with_execution_providers([dispatch(provider, config)]) {
+                Ok(b) => {
+                    println!("✓ Execution provider {} enabled", provider);
+                    registered.push(provider.clone());
+                    builder = b;
+                }
+                Err(e) => {
+                    println!("⚠ Failed to enable execution provider {}: {}", provider, e);
+                    if matches!(provider, Provider::Cuda { .. } | Provider::TensorRT { .. }) {
+                        println!("Please check:");
+                        println!("1. If NVIDIA CUDA Toolkit is properly installed");
+                        println!("2. Run 'nvidia-smi' to verify GPU is detected");
+                        println!("3. Check if CUDA version matches the ONNX Runtime version");
+                    }
+                }
+            }
+        }
+
+        let provider = match registered.first() {
+            Some(provider) => provider.clone(),
+            None if config.fallback_to_cpu => {
+                println!("⚠ No requested execution provider is available, falling back to CPU");
+                Provider::Cpu
+            }
+            None => {
+                let requested: Vec<String> = config.providers.iter().map(Provider::to_string).collect();
+                return Err(format!(
+                    "None of the execution providers [{}] could be enabled and fallback is disabled",
+                    requested.join(", ")
+                ));
+            }
+        };
+
+        // The CPU provider comes last, running whatever the others do not
+        // support, and carries the arena setting
+        if !registered.contains(&Provider::Cpu) {
+            builder = builder
+                .with_execution_providers([dispatch(&Provider::Cpu, config)])
+                .map_err(|e| format!("Failed to set CPU execution provider: {}", e))?;
         }
 
         println!("Loading model from: {}", model_path);
@@ -69,10 +112,9 @@ pub trait OrtBase {
             .commit_from_file(model_path)
             .map_err(|e| format!("Failed to commit from file: {}", e))?;
         
-        println!("✓ Session initialized successfully");
+        println!("✓ Session initialized successfully on {}", provider);
         
-        self.set_sess(session);
-        Ok(())
+        Ok((session, provider))
     }
 
     fn print_info(&self) {
@@ -81,11 +123,11 @@ pub trait OrtBase {
             println!("----------------");
             println!("Input names:");
             for input in &session.inputs {
-                println!("  - {}", input.name);
+                println!("  - {}: {}", input.name, input.input_type);
             }
             println!("Output names:");
             for output in &session.outputs {
-                println!("  - {}", output.name);
+                println!("  - {}: {}", output.name, output.output_type);
             }
             println!("----------------\n");
         } else {
@@ -96,3 +138,42 @@ pub trait OrtBase {
     fn set_sess(&mut self, sess: Session);
     fn sess(&self) -> Option<&Session>;
 }
+
+/// Options of `provider` as set by `config`, failing registration with an
+/// error rather than being skipped.
+fn dispatch(provider: &Provider, config: &OrtConfig) -> ExecutionProviderDispatch {
+    let dispatch = match provider {
+        Provider::Cpu => {
+            let mut cpu = CPUExecutionProvider::default();
+            if config.cpu_arena {
+                cpu = cpu.with_arena_allocator();
+            }
+            cpu.build()
+        }
+        Provider::Cuda { device_id } => {
+            let mut cuda = CUDAExecutionProvider::default()
+                .with_device_id(*device_id)
+                .with_conv_algorithm_search(CUDAExecutionProviderCuDNNConvAlgoSearch::Default);
+            if let Some(limit) = config.gpu_memory_limit {
+                cuda = cuda.with_memory_limit(limit);
+            }
+            cuda.build()
+        }
+        Provider::TensorRT { device_id } => TensorRTExecutionProvider::default()
+            .with_device_id(*device_id)
+            .build(),
+        Provider::OpenVINO { device_type } => {
+            let mut openvino = OpenVINOExecutionProvider::default();
+            if let Some(device_type) = device_type {
+                openvino = openvino.with_device_type(device_type);
+            }
+            openvino.build()
+        }
+        Provider::CoreML => CoreMLExecutionProvider::default().build(),
+        Provider::DirectML { device_id } => DirectMLExecutionProvider::default()
+            .with_device_id(*device_id)
+            .build(),
+        Provider::Xnnpack => XNNPACKExecutionProvider::default().build(),
+    };
+    dispatch.error_on_failure()
+}
@@ -1,27 +1,74 @@
 use std::borrow::Cow;
+use std::ops::Deref;
+use std::sync::{Condvar, Mutex};
 
+use half::f16;
 use ndarray::{ArrayBase, IxDyn, OwnedRepr};
 use ort::{
     session::{Session, SessionInputValue, SessionInputs, SessionOutputs},
+    tensor::TensorElementType,
     value::{Tensor, Value},
 };
 
 use super::ort_base;
-use super::config::OrtConfig;
+use super::config::{OrtConfig, Provider};
+use super::variant::GraphBinding;
 use ort_base::OrtBase;
 
+/// Raw waveform as returned by the model.
+pub type AudioArray = ArrayBase<OwnedRepr<f32>, IxDyn>;
+
+/// Waveform and, when asked for, the per-token durations.
+type InferOutput = (AudioArray, Option<Vec<f32>>);
+
+/// Output names under which Kokoro exports expose per-token durations.
+const DURATION_OUTPUTS: [&str; 3] = ["duration", "durations", "pred_dur"];
+
 pub struct OrtKoko {
-    sess: Option<Session>,
+    /// Sessions of the pool, all loaded from the same model.
+    sessions: Vec<Session>,
+    /// Indices of the sessions not running an inference.
+    free: Mutex<Vec<usize>>,
+    available: Condvar,
     config: OrtConfig,
+    /// Execution provider the sessions run on.
+    provider: Provider,
+    /// Input and output names of the loaded graph.
+    binding: Option<GraphBinding>,
+    /// Name of the predicted durations output, when the graph has one.
+    duration_output: Option<String>,
 }
 
 impl ort_base::OrtBase for OrtKoko {
     fn set_sess(&mut self, sess: Session) {
-        self.sess = Some(sess);
+        self.sessions = vec![sess];
+        self.free = Mutex::new(vec![0]);
     }
 
     fn sess(&self) -> Option<&Session> {
-        self.sess.as_ref()
+        self.sessions.first()
+    }
+}
+
+/// A session checked out of the pool, handed back when dropped.
+struct SessionLease<'a> {
+    koko: &'a OrtKoko,
+    index: usize,
+}
+
+impl Deref for SessionLease<'_> {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.koko.sessions[self.index]
+    }
+}
+
+impl Drop for SessionLease<'_> {
+    fn drop(&mut self) {
+        let mut free = self.koko.free.lock().unwrap_or_else(|e| e.into_inner());
+        free.push(self.index);
+        self.koko.available.notify_one();
     }
 }
 
@@ -30,25 +77,145 @@ impl OrtKoko {
         Self::with_config(model_path, OrtConfig::default())
     }
 
+    /// Loads `config.sessions` sessions of the model. Each one holds its own
+    /// copy of the weights, so memory grows with the pool size.
     pub fn with_config(model_path: String, config: OrtConfig) -> Result<Self, String> {
-        let mut instance = OrtKoko { 
-            sess: None,
+        let mut instance = OrtKoko {
+            sessions: Vec::new(),
+            free: Mutex::new(Vec::new()),
+            available: Condvar::new(),
             config,
+            provider: Provider::Cpu,
+            binding: None,
+            duration_output: None,
+        };
+        instance.provider = instance.load_model_with_config(model_path.clone(), instance.config.clone())?;
+
+        // Checked before loading more sessions, so unknown graphs fail fast
+        let binding = match instance.sess() {
+            Some(sess) => GraphBinding::detect(sess).map_err(|e| format!("{}: {}", model_path, e))?,
+            None => return Err("Session is not initialized.".to_string()),
         };
-        instance.load_model_with_config(model_path, instance.config.clone())?;
+        println!(
+            "✓ Detected {}: {}, {}, {} ({}) -> {}",
+            binding.variant, binding.tokens, binding.style, binding.speed, binding.speed_type, binding.audio
+        );
+        if binding.is_fp16() {
+            println!("✓ fp16 model, inputs and outputs are converted from and to f32");
+        }
+        if matches!(binding.speed_type, TensorElementType::Int32 | TensorElementType::Int64) {
+            println!("⚠ The model takes an integer speed, speeds are rounded to whole numbers of at least 1");
+        }
+        instance.binding = Some(binding);
+
+        for i in 1..instance.config.sessions {
+            println!("Loading session {} of {}...", i + 1, instance.config.sessions);
+            let (session, _) = instance.build_session(&model_path, &instance.config)?;
+            instance.sessions.push(session);
+        }
+        *instance.free.get_mut().unwrap_or_else(|e| e.into_inner()) = (0..instance.sessions.len()).collect();
+
+        instance.duration_output = instance.sess().and_then(|sess| {
+            sess.outputs
+                .iter()
+                .find(|output| DURATION_OUTPUTS.contains(&output.name.as_str()))
+                .map(|output| output.name.clone())
+        });
         Ok(instance)
     }
 
+    /// An instance without a session, for tests that never run inference.
+    #[cfg(test)]
+    pub(crate) fn unloaded() -> Self {
+        OrtKoko {
+            sessions: Vec::new(),
+            free: Mutex::new(Vec::new()),
+            available: Condvar::new(),
+            config: OrtConfig::default(),
+            provider: Provider::Cpu,
+            binding: None,
+            duration_output: None,
+        }
+    }
+
     // 提供配置访问方法
     pub fn config(&self) -> &OrtConfig {
         &self.config
     }
 
+    /// Execution provider running the model, the first of
+    /// `OrtConfig::providers` that could be enabled.
+    pub fn provider(&self) -> &Provider {
+        &self.provider
+    }
+
+    /// Inputs and outputs of the loaded graph.
+    pub fn binding(&self) -> Option<&GraphBinding> {
+        self.binding.as_ref()
+    }
+
+    /// Number of inferences that can run at the same time.
+    pub fn pool_size(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Waits for a session of the pool to be free and takes it.
+    fn checkout(&self) -> Option<SessionLease<'_>> {
+        if self.sessions.is_empty() {
+            return None;
+        }
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(index) = free.pop() {
+                return Some(SessionLease { koko: self, index });
+            }
+            free = self.available.wait(free).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Whether the loaded graph outputs per-token durations, needed for
+    /// `infer_with_durations`.
+    pub fn has_durations(&self) -> bool {
+        self.duration_output.is_some()
+    }
+
     pub fn infer(
         &self,
         tokens: Vec<Vec<i64>>,
         styles: Vec<Vec<f32>>,
-    ) -> Result<ArrayBase<OwnedRepr<f32>, IxDyn>, Box<dyn std::error::Error>> {
+        speed: f32,
+    ) -> Result<AudioArray, Box<dyn std::error::Error>> {
+        let (audio, _) = self.run(tokens, styles, speed, false)?;
+        Ok(audio)
+    }
+
+    /// Runs inference and also returns the predicted duration of every
+    /// input token, in model frames.
+    pub fn infer_with_durations(
+        &self,
+        tokens: Vec<Vec<i64>>,
+        styles: Vec<Vec<f32>>,
+        speed: f32,
+    ) -> Result<(AudioArray, Vec<f32>), Box<dyn std::error::Error>> {
+        if !self.has_durations() {
+            return Err(format!(
+                "the loaded model has no duration output (expected one of: {}), timestamps are not available",
+                DURATION_OUTPUTS.join(", ")
+            )
+            .into());
+        }
+        let (audio, durations) = self.run(tokens, styles, speed, true)?;
+        Ok((audio, durations.unwrap_or_default()))
+    }
+
+    fn run(
+        &self,
+        tokens: Vec<Vec<i64>>,
+        styles: Vec<Vec<f32>>,
+        speed: f32,
+        with_durations: bool,
+    ) -> Result<InferOutput, Box<dyn std::error::Error>> {
+        let binding = self.binding.as_ref().ok_or("Session is not initialized.")?;
         println!("\nStarting inference...");
         let start = std::time::Instant::now();
 
@@ -60,38 +227,63 @@ impl OrtKoko {
         let shape_style = [styles.len(), styles[0].len()];
         println!("Style shape: {:?}", shape_style);
         let style_flat: Vec<f32> = styles.into_iter().flatten().collect();
-        let style = Tensor::from_array((shape_style, style_flat))?;
-        let style_value: SessionInputValue = SessionInputValue::Owned(Value::from(style));
+        let style: Value = match binding.style_type {
+            TensorElementType::Float16 => Tensor::from_array((shape_style, to_f16(&style_flat)))?.into(),
+            _ => Tensor::from_array((shape_style, style_flat))?.into(),
+        };
+        let style_value: SessionInputValue = SessionInputValue::Owned(style);
 
-        let speed = vec![1.0f32; 1];
-        let speed = Tensor::from_array(([1], speed))?;
-        let speed_value: SessionInputValue = SessionInputValue::Owned(Value::from(speed));
+        // Exports differ on the speed dtype, integer ones only take whole speeds
+        let speed: Value = match binding.speed_type {
+            TensorElementType::Int32 => Tensor::from_array(([1], vec![GraphBinding::whole_speed(speed)? as i32]))?.into(),
+            TensorElementType::Int64 => Tensor::from_array(([1], vec![GraphBinding::whole_speed(speed)?]))?.into(),
+            TensorElementType::Float16 => Tensor::from_array(([1], to_f16(&[speed])))?.into(),
+            _ => Tensor::from_array(([1], vec![speed]))?.into(),
+        };
+        let speed_value: SessionInputValue = SessionInputValue::Owned(speed);
 
         let inputs: Vec<(Cow<str>, SessionInputValue)> = vec![
-            (Cow::Borrowed("tokens"), tokens_value),
-            (Cow::Borrowed("style"), style_value),
-            (Cow::Borrowed("speed"), speed_value),
+            (Cow::Borrowed(binding.tokens.as_str()), tokens_value),
+            (Cow::Borrowed(binding.style.as_str()), style_value),
+            (Cow::Borrowed(binding.speed.as_str()), speed_value),
         ];
 
-        if let Some(sess) = &self.sess {
-            println!("Running inference with {} tokens...", shape[1]);
+        if let Some(sess) = self.checkout() {
+            println!("Running inference with {} tokens on session {}...", shape[1], sess.index);
             let outputs: SessionOutputs = sess.run(SessionInputs::from(inputs))?;
-            let output = outputs["audio"]
-                .try_extract_tensor::<f32>()
-                .expect("Failed to extract tensor")
-                .into_owned();
+            let audio = &outputs[binding.audio.as_str()];
+            let output = match binding.audio_type {
+                TensorElementType::Float16 => audio.try_extract_tensor::<f16>()?.mapv(f32::from),
+                _ => audio.try_extract_tensor::<f32>()?.into_owned(),
+            };
+
+            let durations = match (&self.duration_output, with_durations) {
+                (Some(name), true) => {
+                    let value = &outputs[name.as_str()];
+                    // Exports differ on whether durations are floats or frame counts
+                    let durations: Vec<f32> = match value.try_extract_tensor::<f32>() {
+                        Ok(durations) => durations.iter().copied().collect(),
+                        Err(_) => value
+                            .try_extract_tensor::<i64>()?
+                            .iter()
+                            .map(|&d| d as f32)
+                            .collect(),
+                    };
+                    Some(durations)
+                }
+                _ => None,
+            };
             
             let duration = start.elapsed();
-            println!("✓ Inference completed in {:.2?}", duration);
-            if self.config.use_gpu {
-                println!("Using GPU acceleration");
-            } else {
-                println!("Using CPU mode");
-            }
+            println!("✓ Inference completed in {:.2?} on {}", duration, self.provider);
             
-            Ok(output)
+            Ok((output, durations))
         } else {
             Err("Session is not initialized.".into())
         }
     }
 }
+
+fn to_f16(values: &[f32]) -> Vec<f16> {
+    values.iter().map(|&v| f16::from_f32(v)).collect()
+}
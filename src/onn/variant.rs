@@ -0,0 +1,308 @@
+use std::fmt;
+
+use ort::session::Session;
+use ort::tensor::TensorElementType;
+
+/// Kokoro exports whose inputs and outputs are known, named after their
+/// token input since the graph does not tell which weights it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelVariant {
+    /// The export of `kokoro-v0_19.onnx`: `tokens`, `style` and `speed` in,
+    /// `audio` out.
+    Tokens,
+    /// The onnx-community exports, of the v0.19 weights (the fp16, int8 and
+    /// q4 catalog files) as well as of v1.0: `input_ids`, `style` and
+    /// `speed` in, `waveform` or `audio` out, with `speed` as a float or an
+    /// integer and sometimes extra outputs such as the predicted durations.
+    InputIds,
+}
+
+impl fmt::Display for ModelVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelVariant::Tokens => f.write_str("tokens export"),
+            ModelVariant::InputIds => f.write_str("input_ids export"),
+        }
+    }
+}
+
+/// Name and element type of a graph input or output, `None` for values
+/// that are not tensors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorSignature {
+    pub name: String,
+    pub ty: Option<TensorElementType>,
+}
+
+impl TensorSignature {
+    pub fn new(name: &str, ty: Option<TensorElementType>) -> Self {
+        Self {
+            name: name.to_string(),
+            ty,
+        }
+    }
+}
+
+impl fmt::Display for TensorSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ty {
+            Some(ty) => write!(f, "{}: {}", self.name, ty),
+            None => write!(f, "{}: non-tensor", self.name),
+        }
+    }
+}
+
+/// Inputs and outputs of a known variant, each given as the names it may
+/// have and the element types it may take.
+struct KnownGraph {
+    variant: ModelVariant,
+    tokens: &'static [&'static str],
+    style: &'static [&'static str],
+    speed: &'static [&'static str],
+    speed_types: &'static [TensorElementType],
+    audio: &'static [&'static str],
+}
+
+/// Element types accepted for the style input and audio output, fp16
+/// graphs are fed and read through conversions.
+const FLOAT_TYPES: [TensorElementType; 2] = [TensorElementType::Float32, TensorElementType::Float16];
+
+const KNOWN_GRAPHS: [KnownGraph; 2] = [
+    KnownGraph {
+        variant: ModelVariant::Tokens,
+        tokens: &["tokens"],
+        style: &["style"],
+        speed: &["speed"],
+        speed_types: &[TensorElementType::Float32, TensorElementType::Float16],
+        audio: &["audio"],
+    },
+    KnownGraph {
+        variant: ModelVariant::InputIds,
+        tokens: &["input_ids"],
+        style: &["style"],
+        speed: &["speed"],
+        speed_types: &[
+            TensorElementType::Float32,
+            TensorElementType::Float16,
+            TensorElementType::Int32,
+            TensorElementType::Int64,
+        ],
+        audio: &["waveform", "audio"],
+    },
+];
+
+impl KnownGraph {
+    fn describe(&self) -> String {
+        let types = |types: &[TensorElementType]| {
+            types.iter().map(ToString::to_string).collect::<Vec<_>>().join("|")
+        };
+        format!(
+            "{}: inputs [{}: i64, {}: {}, {}: {}], outputs [{}: {}]",
+            self.variant,
+            self.tokens.join("|"),
+            self.style.join("|"),
+            types(&FLOAT_TYPES),
+            self.speed.join("|"),
+            types(self.speed_types),
+            self.audio.join("|"),
+            types(&FLOAT_TYPES)
+        )
+    }
+
+    fn bind(&self, inputs: &[TensorSignature], outputs: &[TensorSignature]) -> Option<GraphBinding> {
+        // Any other input would be left unfed
+        if inputs.len() != 3 {
+            return None;
+        }
+        let find = |values: &[TensorSignature], names: &[&str], types: &[TensorElementType]| {
+            let value = values.iter().find(|v| names.contains(&v.name.as_str()))?;
+            let ty = value.ty.filter(|ty| types.contains(ty))?;
+            Some((value.name.clone(), ty))
+        };
+        let (tokens, _) = find(inputs, self.tokens, &[TensorElementType::Int64])?;
+        let (style, style_type) = find(inputs, self.style, &FLOAT_TYPES)?;
+        let (speed, speed_type) = find(inputs, self.speed, self.speed_types)?;
+        let (audio, audio_type) = find(outputs, self.audio, &FLOAT_TYPES)?;
+
+        Some(GraphBinding {
+            variant: self.variant,
+            tokens,
+            style,
+            style_type,
+            speed,
+            speed_type,
+            audio,
+            audio_type,
+        })
+    }
+}
+
+/// Where a loaded graph takes its inputs and gives its audio.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphBinding {
+    pub variant: ModelVariant,
+    pub tokens: String,
+    pub style: String,
+    /// Element type of the style input, f32 or f16.
+    pub style_type: TensorElementType,
+    pub speed: String,
+    /// Element type of the speed input, integer speeds are rounded.
+    pub speed_type: TensorElementType,
+    pub audio: String,
+    /// Element type of the audio output, f32 or f16.
+    pub audio_type: TensorElementType,
+}
+
+impl GraphBinding {
+    /// Whether any input or output is fp16 and needs converting.
+    pub fn is_fp16(&self) -> bool {
+        [self.style_type, self.speed_type, self.audio_type].contains(&TensorElementType::Float16)
+    }
+
+    /// `speed` rounded for a graph that takes an integer speed. Speeds that
+    /// round to 0 or less are rejected, the graph cannot slow down below 1.
+    pub fn whole_speed(speed: f32) -> Result<i64, String> {
+        let rounded = speed.round();
+        if rounded.is_nan() || rounded < 1.0 {
+            return Err(format!(
+                "the model only takes whole-number speeds and {} rounds to {}, use a speed of 1 or more",
+                speed, rounded
+            ));
+        }
+        Ok(rounded as i64)
+    }
+
+    /// Element types of the style, speed and audio, which tell what
+    /// precision the graph runs at whatever file it was loaded from.
+    pub fn types(&self) -> String {
+        format!("style {}, speed {}, audio {}", self.style_type, self.speed_type, self.audio_type)
+    }
+}
+
+impl GraphBinding {
+    /// Recognizes the graph of `session`.
+    pub fn detect(session: &Session) -> Result<Self, String> {
+        let inputs: Vec<TensorSignature> = session
+            .inputs
+            .iter()
+            .map(|input| TensorSignature::new(&input.name, input.input_type.tensor_type()))
+            .collect();
+        let outputs: Vec<TensorSignature> = session
+            .outputs
+            .iter()
+            .map(|output| TensorSignature::new(&output.name, output.output_type.tensor_type()))
+            .collect();
+        Self::from_signatures(&inputs, &outputs)
+    }
+
+    /// Matches the inputs and outputs of a graph against the known
+    /// variants, failing with what was expected and found.
+    pub fn from_signatures(inputs: &[TensorSignature], outputs: &[TensorSignature]) -> Result<Self, String> {
+        if let Some(binding) = KNOWN_GRAPHS.iter().find_map(|known| known.bind(inputs, outputs)) {
+            return Ok(binding);
+        }
+
+        let list = |values: &[TensorSignature]| {
+            values.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+        };
+        let expected: Vec<String> = KNOWN_GRAPHS.iter().map(|known| format!("  {}", known.describe())).collect();
+        Err(format!(
+            "unsupported model graph with inputs [{}] and outputs [{}], expected one of:\n{}",
+            list(inputs),
+            list(outputs),
+            expected.join("\n")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sig(name: &str, ty: TensorElementType) -> TensorSignature {
+        TensorSignature::new(name, Some(ty))
+    }
+
+    #[test]
+    fn test_detect_tokens_export() {
+        let inputs = [
+            sig("tokens", TensorElementType::Int64),
+            sig("style", TensorElementType::Float32),
+            sig("speed", TensorElementType::Float32),
+        ];
+        let binding = GraphBinding::from_signatures(&inputs, &[sig("audio", TensorElementType::Float32)]).unwrap();
+        assert_eq!(binding.variant, ModelVariant::Tokens);
+        assert_eq!(binding.tokens, "tokens");
+        assert_eq!(binding.audio, "audio");
+    }
+
+    #[test]
+    fn test_detect_input_ids_export() {
+        let inputs = [
+            sig("input_ids", TensorElementType::Int64),
+            sig("style", TensorElementType::Float32),
+            sig("speed", TensorElementType::Int32),
+        ];
+        let outputs = [
+            sig("waveform", TensorElementType::Float32),
+            sig("duration", TensorElementType::Int64),
+        ];
+        let binding = GraphBinding::from_signatures(&inputs, &outputs).unwrap();
+        assert_eq!(binding.variant, ModelVariant::InputIds);
+        assert_eq!(binding.tokens, "input_ids");
+        assert_eq!(binding.speed_type, TensorElementType::Int32);
+        assert_eq!(binding.audio, "waveform");
+        assert!(!binding.is_fp16());
+    }
+
+    #[test]
+    fn test_detect_fp16() {
+        let inputs = [
+            sig("input_ids", TensorElementType::Int64),
+            sig("style", TensorElementType::Float16),
+            sig("speed", TensorElementType::Float16),
+        ];
+        let binding = GraphBinding::from_signatures(&inputs, &[sig("waveform", TensorElementType::Float16)]).unwrap();
+        assert_eq!(binding.style_type, TensorElementType::Float16);
+        assert_eq!(binding.audio_type, TensorElementType::Float16);
+        assert!(binding.is_fp16());
+        assert_eq!(binding.types(), "style f16, speed f16, audio f16");
+    }
+
+    #[test]
+    fn test_whole_speed() {
+        assert_eq!(GraphBinding::whole_speed(1.4), Ok(1));
+        assert_eq!(GraphBinding::whole_speed(0.5), Ok(1));
+        assert_eq!(GraphBinding::whole_speed(2.6), Ok(3));
+        let err = GraphBinding::whole_speed(0.4).unwrap_err();
+        assert!(err.contains("0.4 rounds to 0"), "{}", err);
+        assert!(GraphBinding::whole_speed(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn test_unknown_graph_is_described() {
+        let inputs = [
+            sig("text", TensorElementType::Int64),
+            sig("style", TensorElementType::Float32),
+        ];
+        let err = GraphBinding::from_signatures(&inputs, &[sig("audio", TensorElementType::Float32)]).unwrap_err();
+        assert!(err.contains("inputs [text: i64, style: f32]"), "{}", err);
+        assert!(err.contains("outputs [audio: f32]"), "{}", err);
+        assert!(err.contains("tokens export: inputs [tokens: i64, style: f32|f16, speed: f32|f16]"), "{}", err);
+        assert!(
+            err.contains("input_ids export: inputs [input_ids: i64, style: f32|f16, speed: f32|f16|i32|i64]"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_wrong_dtype_is_rejected() {
+        let inputs = [
+            sig("tokens", TensorElementType::Int32),
+            sig("style", TensorElementType::Float32),
+            sig("speed", TensorElementType::Float32),
+        ];
+        assert!(GraphBinding::from_signatures(&inputs, &[sig("audio", TensorElementType::Float32)]).is_err());
+    }
+}
@@ -1 +1,3 @@
-pub mod openai;
\ No newline at end of file
+pub mod openai;
+pub mod pool;
+pub mod ws;
@@ -1,16 +1,45 @@
 use axum::{
+    body::Body,
     routing::post,
     Router,
     Json,
-    extract::State,
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{rejection::JsonRejection, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing::get,
 };
+use base64::prelude::{Engine, BASE64_STANDARD};
+use futures::{stream, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use tower_http::cors::{Any, CorsLayer};
-use crate::tts::koko::TTSKoko;
+use super::pool::{PoolConfig, PoolError, WorkerPool};
+use crate::tts::encode::{AudioFormat, StreamEncoder};
+use crate::tts::koko::{TTSKoko, TTSOpts, TtsError};
+use crate::tts::stream::TTSStream;
+use crate::tts::subtitles::{cues, Cue, SubtitleFormat};
+use crate::tts::tokenize::UnknownPolicy;
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// Models accepted in the `model` field, all served by the loaded Kokoro model.
+const SUPPORTED_MODELS: [&str; 4] = ["kokoro", "tts-1", "tts-1-hd", "gpt-4o-mini-tts"];
+
+/// OpenAI voice names mapped to the closest Kokoro voices.
+const OPENAI_VOICES: [(&str, &str); 6] = [
+    ("alloy", "af"),
+    ("echo", "am_michael"),
+    ("fable", "bm_lewis"),
+    ("onyx", "am_adam"),
+    ("nova", "af_nicole"),
+    ("shimmer", "af_bella"),
+];
+
+/// Longest accepted `input`, in characters, same as the OpenAI API.
+pub(super) const MAX_INPUT_CHARS: usize = 4096;
 
 #[derive(Deserialize)]
 struct TTSRequest {
@@ -18,75 +47,675 @@ struct TTSRequest {
     input: String,
     voice: Option<String>,
     language: Option<String>,
+    response_format: Option<String>,
+    speed: Option<f32>,
+    normalize: Option<bool>,
+    /// Handling of phonemes the vocab cannot tokenize: drop, substitute or error.
+    unknown_chars: Option<String>,
+    /// Send audio as it is synthesized, defaults to true for wav, pcm and opus.
+    stream: Option<bool>,
+    /// `audio` for a raw audio body, `sse` for base64 audio in Server-Sent Events.
+    stream_format: Option<String>,
+    /// `srt` or `vtt` to answer with JSON holding both the audio and its
+    /// captions, see `SpeechWithCaptions`.
+    captions: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StreamFormat {
+    Audio,
+    Sse,
+}
+
+impl std::str::FromStr for StreamFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "audio" => Ok(StreamFormat::Audio),
+            "sse" => Ok(StreamFormat::Sse),
+            other => Err(format!(
+                "stream_format '{}' is not supported, expected audio or sse",
+                other
+            )),
+        }
+    }
 }
 
+/// Events of the `sse` stream format, in the shape of the OpenAI audio
+/// streaming events.
 #[derive(Serialize)]
-struct TTSResponse {
-    status: String,
-    file_path: String,
+#[serde(tag = "type")]
+enum SpeechEvent {
+    #[serde(rename = "speech.audio.delta")]
+    Delta {
+        /// Base64 of the next bytes of audio in the response format.
+        audio: String,
+    },
+    #[serde(rename = "speech.audio.done")]
+    Done { usage: SpeechUsage },
+    #[serde(rename = "error")]
+    Error { error: ErrorDetail },
+}
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+struct SpeechUsage {
+    characters: usize,
+    phoneme_tokens: usize,
+    audio_seconds: f32,
+}
+
+/// Audio bytes encoded from a synthesized chunk, with what went into them.
+struct EncodedChunk {
+    bytes: Vec<u8>,
+    samples: usize,
+    phonemes_len: usize,
+}
+
+/// Response to a request with `captions`.
+#[derive(Serialize)]
+struct SpeechWithCaptions {
+    /// Base64 of the audio in the response format.
+    audio: String,
+    content_type: &'static str,
+    /// The caption file, in the requested format.
+    captions: String,
+    /// The same captions as structured cues.
+    cues: Vec<Cue>,
+}
+
+/// Error body in the shape returned by the OpenAI API.
+#[derive(Serialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail {
+    message: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    param: Option<&'static str>,
+    code: Option<&'static str>,
+}
+
+struct ApiError {
+    status: StatusCode,
+    detail: ErrorDetail,
+    /// Sent as `Retry-After` when the server is too busy.
+    retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    fn invalid_request(param: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            detail: ErrorDetail {
+                message: message.into(),
+                kind: "invalid_request_error",
+                param: Some(param),
+                code: None,
+            },
+            retry_after: None,
+        }
+    }
+
+    fn with_code(mut self, code: &'static str) -> Self {
+        self.detail.code = Some(code);
+        self
+    }
+
+    fn server_error(message: impl Into<String>) -> Self {
+        ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            detail: ErrorDetail {
+                message: message.into(),
+                kind: "server_error",
+                param: None,
+                code: None,
+            },
+            retry_after: None,
+        }
+    }
+
+    /// A request the worker pool could not serve. When every worker is
+    /// busy the client is told to come back later with a 503, the whole
+    /// server being at capacity rather than the client sending too much. A
+    /// request that ran out of time gets a 504.
+    fn pool(error: PoolError, pool: &WorkerPool) -> Self {
+        match error {
+            PoolError::QueueFull => ApiError {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                detail: ErrorDetail {
+                    message: error.to_string(),
+                    kind: "server_error",
+                    param: None,
+                    code: Some("queue_full"),
+                },
+                retry_after: Some(pool.retry_after()),
+            },
+            PoolError::TimedOut => ApiError {
+                status: StatusCode::GATEWAY_TIMEOUT,
+                detail: ErrorDetail {
+                    message: format!(
+                        "the request did not finish within {}s",
+                        pool.config().timeout.as_secs()
+                    ),
+                    kind: "server_error",
+                    param: None,
+                    code: Some("timeout"),
+                },
+                retry_after: None,
+            },
+            PoolError::Failed => ApiError::server_error(error.to_string()),
+        }
+    }
+
+    /// A synthesis that failed, a 400 naming the option at fault when the
+    /// request asked for something that cannot be synthesized.
+    fn tts(error: TtsError) -> Self {
+        match error {
+            TtsError::InvalidInput { param, message } => ApiError::invalid_request(param, message),
+            TtsError::Failed(message) => ApiError::server_error(message),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(ErrorBody { error: self.detail })).into_response();
+        if let Some(retry_after) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after.as_secs()));
+        }
+        response
+    }
 }
 
 #[derive(Clone)]
 pub struct AppState {
-    tts: Arc<TTSKoko>,
+    pub(super) tts: Arc<TTSKoko>,
+    pub(super) pool: Arc<WorkerPool>,
 }
 
 async fn health_check() -> &'static str {
     "OK"
 }
 
+/// The `response_format` of requests that leave it out: mp3 like OpenAI,
+/// which is sent whole, or pcm for Server-Sent Events, which need a format
+/// that can be streamed.
+fn default_format(stream_format: StreamFormat) -> AudioFormat {
+    match stream_format {
+        StreamFormat::Sse => AudioFormat::Pcm,
+        StreamFormat::Audio => AudioFormat::Mp3,
+    }
+}
+
+/// Picks the language from the script of the input when none is given.
+pub(super) fn detect_language(input: &str) -> String {
+    // 自动检测语言
+    if input.chars().any(|c| c as u32 >= 0x4E00 && c as u32 <= 0x9FFF) {
+        "zh-cn".to_string()
+    } else if input.chars().any(|c| c as u32 >= 0x3040 && c as u32 <= 0x30FF) {
+        "ja-jp".to_string()
+    } else if input.chars().any(|c| c as u32 >= 0x0400 && c as u32 <= 0x04FF) {
+        "de-de".to_string()
+    } else {
+        "en-us".to_string()
+    }
+}
+
+/// Maps OpenAI voice names to Kokoro voices, other names are used as is.
+pub(super) fn resolve_voice(voice: &str) -> &str {
+    OPENAI_VOICES
+        .iter()
+        .find(|(openai, _)| *openai == voice)
+        .map_or(voice, |(_, kokoro)| kokoro)
+}
+
+/// Answers `/v1/audio/speech`, telling in `x-execution-provider` where the
+/// model runs.
 async fn text_to_speech(
     State(state): State<AppState>,
-    Json(payload): Json<TTSRequest>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let voice = payload.voice.unwrap_or_else(|| "af_sky".to_string());
-    
+    payload: Result<Json<TTSRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let provider = state.tts.provider().to_string();
+    let mut response = speech(state, payload).await?;
+    if let Ok(value) = HeaderValue::from_str(&provider) {
+        response.headers_mut().insert("x-execution-provider", value);
+    }
+    Ok(response)
+}
+
+async fn speech(
+    state: AppState,
+    payload: Result<Json<TTSRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(payload) = payload.map_err(|e| ApiError::invalid_request("body", e.body_text()))?;
+
+    if !SUPPORTED_MODELS.contains(&payload.model.as_str()) {
+        return Err(ApiError::invalid_request(
+            "model",
+            format!(
+                "model '{}' is not supported, expected one of: {}",
+                payload.model,
+                SUPPORTED_MODELS.join(", ")
+            ),
+        )
+        .with_code("model_not_found"));
+    }
+
+    let input = payload.input.trim();
+    if input.is_empty() {
+        return Err(ApiError::invalid_request("input", "input must not be empty"));
+    }
+    if input.chars().count() > MAX_INPUT_CHARS {
+        return Err(ApiError::invalid_request(
+            "input",
+            format!("input must be at most {} characters", MAX_INPUT_CHARS),
+        ));
+    }
+
+    let voice = resolve_voice(payload.voice.as_deref().unwrap_or("af_sky"));
+    state
+        .tts
+        .validate_style(voice)
+        .map_err(|e| ApiError::invalid_request("voice", e).with_code("voice_not_found"))?;
+
+    let stream_format = payload
+        .stream_format
+        .as_deref()
+        .map_or(Ok(StreamFormat::Audio), str::parse::<StreamFormat>)
+        .map_err(|e| ApiError::invalid_request("stream_format", e))?;
+
+    let format = payload
+        .response_format
+        .as_deref()
+        .map_or(Ok(default_format(stream_format)), str::parse::<AudioFormat>)
+        .map_err(|e| ApiError::invalid_request("response_format", e))?;
+    if stream_format == StreamFormat::Sse && payload.stream == Some(false) {
+        return Err(ApiError::invalid_request(
+            "stream",
+            "stream_format sse is always streamed, stream must not be false",
+        ));
+    }
+    if stream_format == StreamFormat::Sse && !format.is_streamable() {
+        return Err(ApiError::invalid_request(
+            "response_format",
+            format!("{} output cannot be streamed, use wav, pcm or opus", format),
+        ));
+    }
+
+    let captions = payload
+        .captions
+        .as_deref()
+        .map(str::parse::<SubtitleFormat>)
+        .transpose()
+        .map_err(|e| ApiError::invalid_request("captions", e))?;
+    if captions.is_some() && (stream_format == StreamFormat::Sse || payload.stream == Some(true)) {
+        return Err(ApiError::invalid_request(
+            "captions",
+            "captions are returned with the complete audio and cannot be streamed",
+        ));
+    }
+
+    let stream = payload
+        .stream
+        .unwrap_or(format.is_streamable() && captions.is_none());
+    if stream && !format.is_streamable() {
+        return Err(ApiError::invalid_request(
+            "stream",
+            format!("{} output cannot be streamed, use wav, pcm or opus", format),
+        ));
+    }
+
+    let speed = payload.speed.unwrap_or(1.0);
+    TTSKoko::validate_speed(speed).map_err(|e| ApiError::invalid_request("speed", e))?;
+    let unknown_policy = payload
+        .unknown_chars
+        .as_deref()
+        .unwrap_or("drop")
+        .parse::<UnknownPolicy>()
+        .map_err(|e| ApiError::invalid_request("unknown_chars", e))?;
+    let opts = TTSOpts::new()
+        .with_speed(speed)
+        .with_normalize(payload.normalize.unwrap_or(true))
+        .with_unknown_policy(unknown_policy);
+
     // 获取语言设置，如果未指定则自动检测
-    let lang = if let Some(lang) = payload.language {
-        lang
-    } else {
-        // 自动检测语言
-        if payload.input.chars().any(|c| c as u32 >= 0x4E00 && c as u32 <= 0x9FFF) {
-            "zh-cn".to_string()
-        } else if payload.input.chars().any(|c| c as u32 >= 0x3040 && c as u32 <= 0x30FF) {
-            "ja-jp".to_string()
-        } else if payload.input.chars().any(|c| c as u32 >= 0x0400 && c as u32 <= 0x04FF) {
-            "de-de".to_string()
-        } else {
-            "en-us".to_string()
+    let lang = payload.language.unwrap_or_else(|| detect_language(input));
+
+    if stream_format == StreamFormat::Sse {
+        let chunks = stream_speech(&state, input, &lang, voice, opts, format).await?;
+        return Ok(sse_speech(chunks, input.chars().count()).into_response());
+    }
+    if stream {
+        let chunks = stream_speech(&state, input, &lang, voice, opts, format).await?;
+        let body = chunks.map(|chunk| chunk.map(|c| c.bytes).map_err(std::io::Error::other));
+        return Ok(([(header::CONTENT_TYPE, format.content_type())], Body::from_stream(body)).into_response());
+    }
+
+    // Process TTS request with language, off the async runtime
+    let tts = Arc::clone(&state.tts);
+    let (input, voice) = (input.to_string(), voice.to_string());
+    let (audio, body) = state
+        .pool
+        .run(move || {
+            let audio = tts.tts(&input, &lang, &voice, &opts).map_err(TtsError::from_boxed)?;
+            let body = audio.encode(format).map_err(|e| TtsError::Failed(e.to_string()))?;
+            Ok::<_, TtsError>((audio, body))
+        })
+        .await
+        .map_err(|e| ApiError::pool(e, &state.pool))?
+        .map_err(ApiError::tts)?;
+
+    let mut response = match captions {
+        Some(captions) => {
+            let cues = cues(&audio);
+            Json(SpeechWithCaptions {
+                audio: BASE64_STANDARD.encode(&body),
+                content_type: format.content_type(),
+                captions: captions.render(&cues),
+                cues,
+            })
+            .into_response()
         }
+        None => ([(header::CONTENT_TYPE, format.content_type())], body).into_response(),
     };
-    
-    // Generate unique output filename
-    let output_path = format!("output_{}.wav", std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs());
-
-    // Process TTS request with language
-    if let Err(_) = state.tts.tts(&payload.input, &lang, &voice) {
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    if !audio.unknown.is_empty() {
+        // Header values must be ASCII, so characters are listed by code point
+        let unknown: Vec<String> = audio
+            .unknown
+            .iter()
+            .map(|u| format!("U+{:04X}@{}", u.ch as u32, u.position))
+            .collect();
+        if let Ok(value) = HeaderValue::from_str(&unknown.join(", ")) {
+            response
+                .headers_mut()
+                .insert("x-unsupported-phonemes", value);
+        }
     }
+    Ok(response)
+}
+
+/// Synthesizes sentence by sentence, encoding each chunk in `format` as it
+/// is ready.
+///
+/// The first chunk is awaited before answering, so requests that fail on
+/// their first sentence still get an error response, and the request
+/// timeout applies until then. Later failures end the stream with an error.
+async fn stream_speech(
+    state: &AppState,
+    input: &str,
+    lang: &str,
+    voice: &str,
+    opts: TTSOpts,
+    format: AudioFormat,
+) -> Result<impl Stream<Item = Result<EncodedChunk, String>> + Send + 'static, ApiError> {
+    let stream = TTSStream::new(Arc::clone(&state.tts), input, lang, voice, opts).map_err(ApiError::tts)?;
+    let mut encoder = StreamEncoder::new(format, TTSKoko::SAMPLE_RATE).map_err(ApiError::server_error)?;
+    let mut audio = state
+        .pool
+        .stream(stream)
+        .map_err(|e| ApiError::pool(e, &state.pool))?;
+
+    let first = match tokio::time::timeout(state.pool.config().timeout, audio.next()).await {
+        Ok(Some(chunk)) => chunk.map_err(ApiError::tts)?,
+        Ok(None) => return Err(ApiError::server_error("no audio was synthesized")),
+        Err(_) => return Err(ApiError::pool(PoolError::TimedOut, &state.pool)),
+    };
+    let first = EncodedChunk {
+        bytes: encoder.push(&first.samples).map_err(ApiError::server_error)?,
+        samples: first.samples.len(),
+        phonemes_len: first.phonemes_len,
+    };
+
+    let rest = stream::unfold(Some((audio, encoder)), |state| async move {
+        let (mut audio, mut encoder) = state?;
+        match audio.next().await {
+            Some(Ok(chunk)) => {
+                let encoded = encoder.push(&chunk.samples).map(|bytes| EncodedChunk {
+                    bytes,
+                    samples: chunk.samples.len(),
+                    phonemes_len: chunk.phonemes_len,
+                });
+                Some((encoded, Some((audio, encoder))))
+            }
+            Some(Err(e)) => {
+                eprintln!("Streaming synthesis failed: {}", e);
+                Some((Err(e.to_string()), None))
+            }
+            None => {
+                let encoded = encoder.finish().map(|bytes| EncodedChunk {
+                    bytes,
+                    samples: 0,
+                    phonemes_len: 0,
+                });
+                Some((encoded, None))
+            }
+        }
+    });
+    Ok(stream::once(async { Ok(first) }).chain(rest))
+}
+
+/// Wraps encoded chunks in `speech.audio.delta` events, ending with a
+/// `speech.audio.done` event carrying the usage, or an `error` event.
+fn sse_speech(
+    chunks: impl Stream<Item = Result<EncodedChunk, String>> + Send + 'static,
+    characters: usize,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let usage = SpeechUsage {
+        characters,
+        ..SpeechUsage::default()
+    };
+    let events = stream::unfold(Some((Box::pin(chunks), usage)), |state| async move {
+        let (mut chunks, mut usage) = state?;
+        loop {
+            let event = match chunks.next().await {
+                Some(Ok(chunk)) => {
+                    usage.phoneme_tokens += chunk.phonemes_len;
+                    usage.audio_seconds += chunk.samples as f32 / TTSKoko::SAMPLE_RATE as f32;
+                    if chunk.bytes.is_empty() {
+                        continue;
+                    }
+                    SpeechEvent::Delta {
+                        audio: BASE64_STANDARD.encode(&chunk.bytes),
+                    }
+                }
+                Some(Err(e)) => {
+                    let error = ApiError::server_error(e).detail;
+                    return Some((sse_event(&SpeechEvent::Error { error }), None));
+                }
+                None => return Some((sse_event(&SpeechEvent::Done { usage }), None)),
+            };
+            return Some((sse_event(&event), Some((chunks, usage))));
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
 
-    Ok(Json(TTSResponse {
-        status: "success".to_string(),
-        file_path: output_path,
-    }))
+fn sse_event(event: &SpeechEvent) -> Result<Event, Infallible> {
+    Ok(Event::default().data(serde_json::to_string(event).expect("events always serialize")))
 }
 
-pub async fn create_server(tts: TTSKoko) -> Router {
+pub async fn create_server(tts: TTSKoko, pool_config: PoolConfig) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers(Any);
 
-    let app_state = AppState { 
-        tts: Arc::new(tts)
+    let app_state = AppState {
+        tts: Arc::new(tts),
+        pool: Arc::new(WorkerPool::new(pool_config)),
     };
 
     Router::new()
         .route("/", get(health_check))
         .route("/v1/audio/speech", post(text_to_speech))
+        .route("/v1/audio/speech/ws", get(super::ws::speech_ws))
         .layer(cors)
         .with_state(app_state)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_body_shape() {
+        let error = ApiError::invalid_request("voice", "voice 'x' not found").with_code("voice_not_found");
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+
+        let body = serde_json::to_value(ErrorBody { error: error.detail }).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": {
+                    "message": "voice 'x' not found",
+                    "type": "invalid_request_error",
+                    "param": "voice",
+                    "code": "voice_not_found"
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_tts_errors_blame_the_request_or_the_server() {
+        let error = ApiError::tts(TtsError::invalid("unknown_chars", "unsupported phonemes: ʔ"));
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.detail.kind, "invalid_request_error");
+        assert_eq!(error.detail.param, Some("unknown_chars"));
+
+        let boxed: Box<dyn std::error::Error> = TtsError::invalid("voice", "voice 'x' not found").into();
+        assert_eq!(ApiError::tts(TtsError::from_boxed(boxed)).detail.param, Some("voice"));
+
+        let error = ApiError::tts(TtsError::from_boxed("inference failed".into()));
+        assert_eq!(error.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.detail.kind, "server_error");
+    }
+
+    #[test]
+    fn test_busy_response_has_retry_after() {
+        let pool = WorkerPool::new(PoolConfig::new().with_workers(1).with_queue_size(1));
+        let response = ApiError::pool(PoolError::QueueFull, &pool).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let retry_after = response.headers()[header::RETRY_AFTER].to_str().unwrap();
+        assert_eq!(retry_after, pool.retry_after().as_secs().to_string());
+
+        let response = ApiError::pool(PoolError::Failed, &pool).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn test_speech_event_shape() {
+        let delta = serde_json::to_value(SpeechEvent::Delta { audio: "AAE=".to_string() }).unwrap();
+        assert_eq!(delta, serde_json::json!({"type": "speech.audio.delta", "audio": "AAE="}));
+
+        let done = serde_json::to_value(SpeechEvent::Done {
+            usage: SpeechUsage {
+                characters: 5,
+                phoneme_tokens: 12,
+                audio_seconds: 0.5,
+            },
+        })
+        .unwrap();
+        assert_eq!(
+            done,
+            serde_json::json!({
+                "type": "speech.audio.done",
+                "usage": {"characters": 5, "phoneme_tokens": 12, "audio_seconds": 0.5}
+            })
+        );
+    }
+
+    #[test]
+    fn test_captions_response_shape() {
+        let cues = vec![Cue { start: 0.0, end: 1.25, text: "Hi.".to_string() }];
+        let body = serde_json::to_value(SpeechWithCaptions {
+            audio: "AAE=".to_string(),
+            content_type: AudioFormat::Mp3.content_type(),
+            captions: SubtitleFormat::Vtt.render(&cues),
+            cues,
+        })
+        .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "audio": "AAE=",
+                "content_type": "audio/mpeg",
+                "captions": "WEBVTT\n\n00:00:00.000 --> 00:00:01.250\nHi.\n\n",
+                "cues": [{"start": 0.0, "end": 1.25, "text": "Hi."}]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sse_events_carry_audio_and_usage() {
+        let chunks = stream::iter(vec![
+            Ok(EncodedChunk { bytes: vec![0, 1], samples: 12000, phonemes_len: 7 }),
+            Ok(EncodedChunk { bytes: Vec::new(), samples: 0, phonemes_len: 0 }),
+            Ok(EncodedChunk { bytes: vec![2], samples: 12000, phonemes_len: 3 }),
+        ]);
+        let body = sse_speech(chunks, 11).into_response().into_body();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+
+        let events: Vec<serde_json::Value> = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                serde_json::json!({"type": "speech.audio.delta", "audio": "AAE="}),
+                serde_json::json!({"type": "speech.audio.delta", "audio": "Ag=="}),
+                serde_json::json!({
+                    "type": "speech.audio.done",
+                    "usage": {"characters": 11, "phoneme_tokens": 10, "audio_seconds": 1.0}
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sse_stream_ends_with_error_event() {
+        let chunks = stream::iter(vec![
+            Ok(EncodedChunk { bytes: vec![0], samples: 1, phonemes_len: 1 }),
+            Err("inference failed".to_string()),
+        ]);
+        let body = sse_speech(chunks, 1).into_response().into_body();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+
+        let last: serde_json::Value =
+            serde_json::from_str(text.lines().rev().find_map(|l| l.strip_prefix("data: ")).unwrap()).unwrap();
+        assert_eq!(last["type"], "error");
+        assert_eq!(last["error"]["message"], "inference failed");
+    }
+
+    #[test]
+    fn test_resolve_voice() {
+        assert_eq!(resolve_voice("alloy"), "af");
+        assert_eq!(resolve_voice("af_sky"), "af_sky");
+        assert_eq!(resolve_voice("af_sarah.4+af_nicole.6"), "af_sarah.4+af_nicole.6");
+    }
+
+    #[test]
+    fn test_default_format() {
+        assert_eq!(default_format(StreamFormat::Audio), AudioFormat::Mp3);
+        assert!(default_format(StreamFormat::Sse).is_streamable());
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(detect_language("Hello"), "en-us");
+        assert_eq!(detect_language("你好"), "zh-cn");
+        assert_eq!(detect_language("こんにちは"), "ja-jp");
+    }
+}
@@ -0,0 +1,279 @@
+use std::ops::Deref;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+
+use crate::tts::koko::TTSKoko;
+use crate::tts::stream::{AudioStream, TTSStream};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Sizing of the inference worker pool.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// Number of requests synthesized at the same time.
+    pub workers: usize,
+    /// Requests waiting for a worker before new ones are turned away.
+    pub queue_size: usize,
+    /// Longest a request may take, waiting in the queue included.
+    pub timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            workers: 2,
+            queue_size: 16,
+            timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    pub fn with_queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolError {
+    /// Every worker is busy and the queue is full.
+    QueueFull,
+    /// The request did not finish within `PoolConfig::timeout`.
+    TimedOut,
+    /// The job panicked or the pool is gone.
+    Failed,
+}
+
+impl std::fmt::Display for PoolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PoolError::QueueFull => f.write_str("all workers are busy and the request queue is full"),
+            PoolError::TimedOut => f.write_str("the request timed out"),
+            PoolError::Failed => f.write_str("synthesis worker failed"),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
+/// Runs inference on dedicated threads, so the async runtime stays free to
+/// answer other requests.
+///
+/// Jobs wait in a bounded queue, when it is full they are rejected at once
+/// rather than piling up. A job whose caller gave up before it started is
+/// skipped.
+pub struct WorkerPool {
+    tx: SyncSender<Job>,
+    config: PoolConfig,
+    /// Moving average of the job run time, in milliseconds.
+    avg_job_ms: Arc<AtomicU64>,
+}
+
+impl WorkerPool {
+    pub fn new(config: PoolConfig) -> Self {
+        let (tx, rx) = sync_channel::<Job>(config.queue_size);
+        let rx = Arc::new(Mutex::new(rx));
+        for i in 0..config.workers.max(1) {
+            let rx = Arc::clone(&rx);
+            thread::Builder::new()
+                .name(format!("koko-worker-{}", i))
+                .spawn(move || worker_loop(rx))
+                .expect("failed to spawn synthesis worker");
+        }
+        println!(
+            "Started {} synthesis workers, queue of {}, {}s timeout",
+            config.workers.max(1),
+            config.queue_size,
+            config.timeout.as_secs()
+        );
+
+        WorkerPool {
+            tx,
+            config,
+            avg_job_ms: Arc::new(AtomicU64::new(1000)),
+        }
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.config
+    }
+
+    /// Queues `job`, failing at once if the queue is full.
+    pub fn submit<F>(&self, job: F) -> Result<(), PoolError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.tx.try_send(Box::new(job)).map_err(|e| match e {
+            TrySendError::Full(_) => PoolError::QueueFull,
+            TrySendError::Disconnected(_) => PoolError::Failed,
+        })
+    }
+
+    /// Runs `job` on a worker and waits for its result, at most
+    /// `PoolConfig::timeout`.
+    pub async fn run<T, F>(&self, job: F) -> Result<T, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        let avg_job_ms = Arc::clone(&self.avg_job_ms);
+
+        self.submit(move || {
+            if result_tx.is_closed() {
+                // Timed out while queued
+                return;
+            }
+            let start = Instant::now();
+            let result = job();
+            record_job_time(&avg_job_ms, start.elapsed());
+            let _ = result_tx.send(result);
+        })?;
+
+        match tokio::time::timeout(self.config.timeout, result_rx).await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(_)) => Err(PoolError::Failed),
+            Err(_) => Err(PoolError::TimedOut),
+        }
+    }
+
+    /// Synthesizes `stream` on a worker, which stays busy until the stream is
+    /// done or dropped, or its consumer has not read for
+    /// `PoolConfig::timeout`.
+    pub fn stream<T>(&self, stream: TTSStream<T>) -> Result<AudioStream, PoolError>
+    where
+        T: Deref<Target = TTSKoko> + Send + 'static,
+    {
+        let (audio, producer) = AudioStream::pipe(stream, Some(self.config.timeout));
+        self.submit(producer)?;
+        Ok(audio)
+    }
+
+    /// Suggested wait before retrying a rejected request: roughly the time
+    /// for the jobs ahead of it to go through the workers.
+    pub fn retry_after(&self) -> Duration {
+        let avg_ms = self.avg_job_ms.load(Ordering::Relaxed);
+        let rounds = self.config.queue_size.div_ceil(self.config.workers.max(1)).max(1) as u64;
+        Duration::from_secs((avg_ms * rounds).div_ceil(1000).max(1))
+    }
+}
+
+fn worker_loop(rx: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        let job = {
+            let Ok(rx) = rx.lock() else { return };
+            match rx.recv() {
+                Ok(job) => job,
+                // The pool was dropped
+                Err(_) => return,
+            }
+        };
+        if catch_unwind(AssertUnwindSafe(job)).is_err() {
+            eprintln!("Synthesis job panicked on {:?}", thread::current().name());
+        }
+    }
+}
+
+fn record_job_time(avg_job_ms: &AtomicU64, elapsed: Duration) {
+    let ms = elapsed.as_millis() as u64;
+    let avg = avg_job_ms.load(Ordering::Relaxed);
+    avg_job_ms.store((avg * 3 + ms) / 4, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc;
+
+    fn pool(workers: usize, queue_size: usize, timeout_ms: u64) -> WorkerPool {
+        WorkerPool::new(
+            PoolConfig::new()
+                .with_workers(workers)
+                .with_queue_size(queue_size)
+                .with_timeout(Duration::from_millis(timeout_ms)),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_run_returns_result() {
+        let pool = pool(1, 1, 1000);
+        assert_eq!(pool.run(|| 2 + 2).await, Ok(4));
+    }
+
+    #[tokio::test]
+    async fn test_queue_full_is_rejected() {
+        let pool = pool(1, 1, 1000);
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+        // Occupy the only worker, then fill the queue
+        pool.submit(move || {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        pool.submit(|| {}).unwrap();
+
+        assert_eq!(pool.submit(|| {}), Err(PoolError::QueueFull));
+        assert_eq!(pool.run(|| ()).await, Err(PoolError::QueueFull));
+        release_tx.send(()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_timeout_skips_queued_job() {
+        let pool = pool(1, 2, 50);
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        pool.submit(move || {
+            let _ = release_rx.recv();
+        })
+        .unwrap();
+
+        let ran = Arc::new(AtomicBool::new(false));
+        let job_ran = Arc::clone(&ran);
+        let result = pool.run(move || job_ran.store(true, Ordering::Relaxed)).await;
+        assert_eq!(result, Err(PoolError::TimedOut));
+
+        release_tx.send(()).unwrap();
+        assert_eq!(pool.run(|| ()).await, Ok(()));
+        assert!(!ran.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_worker_survives_panic() {
+        let pool = pool(1, 1, 1000);
+        let result: Result<i32, _> = pool.run(|| panic!("boom")).await;
+        assert_eq!(result, Err(PoolError::Failed));
+        assert_eq!(pool.run(|| 1).await, Ok(1));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let pool = pool(2, 4, 1000);
+        pool.avg_job_ms.store(1500, Ordering::Relaxed);
+        assert_eq!(pool.retry_after(), Duration::from_secs(3));
+        pool.avg_job_ms.store(0, Ordering::Relaxed);
+        assert_eq!(pool.retry_after(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,542 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+};
+use futures::{stream::SplitSink, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use super::openai::{detect_language, resolve_voice, AppState, MAX_INPUT_CHARS};
+use super::pool::WorkerPool;
+use crate::tts::audio::ms_to_samples;
+use crate::tts::chunk::{complete_sentences_len, split_text_sentences};
+use crate::tts::encode::{AudioFormat, StreamEncoder};
+use crate::tts::koko::{TTSKoko, TTSOpts, TtsError};
+use crate::tts::phonemizer::Phonemizer;
+use crate::tts::stream::{AudioChunk, AudioStream, TTSStream};
+use crate::tts::tokenize::UnknownPolicy;
+
+/// Messages sent by the client as JSON text frames.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// A fragment of text, synthesized once it completes a sentence.
+    Text { text: String },
+    /// Changes the synthesis settings for the sentences that follow.
+    Config {
+        voice: Option<String>,
+        speed: Option<f32>,
+        language: Option<String>,
+        normalize: Option<bool>,
+        unknown_chars: Option<String>,
+    },
+    /// Synthesizes the buffered text even if it does not end a sentence.
+    Flush,
+    /// Drops the buffered text and stops the synthesis in progress.
+    Cancel,
+}
+
+/// Events sent to the client as JSON text frames, audio goes in binary
+/// frames between `chunk_start` and `chunk_end`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerEvent {
+    Ready {
+        sample_rate: u32,
+        format: &'static str,
+    },
+    ChunkStart {
+        id: usize,
+        /// The sentence the chunk was synthesized from.
+        text: String,
+    },
+    ChunkEnd {
+        id: usize,
+        samples: usize,
+        audio_ms: u64,
+        /// Time from the sentence being complete to its audio being ready.
+        latency_ms: u64,
+    },
+    /// All text received before the flush has been sent as audio.
+    Flushed,
+    Cancelled,
+    Error {
+        message: String,
+    },
+}
+
+/// Synthesis settings of a connection, changed with `config` messages.
+struct WsConfig {
+    voice: String,
+    speed: f32,
+    /// Detected per sentence when not set.
+    language: Option<String>,
+    normalize: bool,
+    unknown_policy: UnknownPolicy,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        WsConfig {
+            voice: "af_sky".to_string(),
+            speed: 1.0,
+            language: None,
+            normalize: true,
+            unknown_policy: UnknownPolicy::default(),
+        }
+    }
+}
+
+enum Pending {
+    Sentence { text: String, queued_at: Instant },
+    /// Answered with `flushed` once everything queued before it is sent.
+    Flush,
+}
+
+/// A sentence being synthesized.
+struct Job {
+    text: String,
+    queued_at: Instant,
+    stream: AudioStream,
+    first_chunk: bool,
+}
+
+type WsSender = SplitSink<WebSocket, Message>;
+
+struct WsSession {
+    tts: Arc<TTSKoko>,
+    pool: Arc<WorkerPool>,
+    config: WsConfig,
+    /// Text received since the last complete sentence.
+    buffer: String,
+    queue: VecDeque<Pending>,
+    current: Option<Job>,
+    next_chunk_id: usize,
+    audio_sent: bool,
+}
+
+impl WsSession {
+    fn new(tts: Arc<TTSKoko>, pool: Arc<WorkerPool>) -> Self {
+        WsSession {
+            tts,
+            pool,
+            config: WsConfig::default(),
+            buffer: String::new(),
+            queue: VecDeque::new(),
+            current: None,
+            next_chunk_id: 0,
+            audio_sent: false,
+        }
+    }
+
+    /// Applies a client message, returning the events to answer with.
+    fn handle(&mut self, message: ClientMessage) -> Vec<ServerEvent> {
+        match message {
+            ClientMessage::Text { text } => {
+                self.buffer.push_str(&text);
+                let mut end = complete_sentences_len(&self.buffer);
+                if end == 0 && self.buffer.chars().count() > MAX_INPUT_CHARS {
+                    // No sentence boundary in sight, do not buffer forever
+                    end = self.buffer.len();
+                }
+                let complete: String = self.buffer.drain(..end).collect();
+                self.queue_sentences(&complete);
+                Vec::new()
+            }
+            ClientMessage::Config {
+                voice,
+                speed,
+                language,
+                normalize,
+                unknown_chars,
+            } => match self.update_config(voice, speed, language, normalize, unknown_chars) {
+                Ok(()) => Vec::new(),
+                Err(message) => vec![ServerEvent::Error { message }],
+            },
+            ClientMessage::Flush => {
+                let rest = std::mem::take(&mut self.buffer);
+                self.queue_sentences(&rest);
+                self.queue.push_back(Pending::Flush);
+                Vec::new()
+            }
+            ClientMessage::Cancel => {
+                // Dropping the stream stops its synthesis
+                self.current = None;
+                self.queue.clear();
+                self.buffer.clear();
+                vec![ServerEvent::Cancelled]
+            }
+        }
+    }
+
+    fn queue_sentences(&mut self, text: &str) {
+        let queued_at = Instant::now();
+        for sentence in split_text_sentences(text) {
+            self.queue.push_back(Pending::Sentence {
+                text: sentence.to_string(),
+                queued_at,
+            });
+        }
+    }
+
+    fn update_config(
+        &mut self,
+        voice: Option<String>,
+        speed: Option<f32>,
+        language: Option<String>,
+        normalize: Option<bool>,
+        unknown_chars: Option<String>,
+    ) -> Result<(), String> {
+        // Validate everything before changing anything
+        let voice = voice.map(|v| resolve_voice(&v).to_string());
+        if let Some(voice) = &voice {
+            self.tts.validate_style(voice)?;
+        }
+        if let Some(speed) = speed {
+            TTSKoko::validate_speed(speed)?;
+        }
+        if let Some(language) = &language {
+            Phonemizer::new(language)?;
+        }
+        let unknown_policy = unknown_chars
+            .map(|u| u.parse::<UnknownPolicy>())
+            .transpose()?;
+
+        if let Some(voice) = voice {
+            self.config.voice = voice;
+        }
+        if let Some(speed) = speed {
+            self.config.speed = speed;
+        }
+        if language.is_some() {
+            self.config.language = language;
+        }
+        if let Some(normalize) = normalize {
+            self.config.normalize = normalize;
+        }
+        if let Some(unknown_policy) = unknown_policy {
+            self.config.unknown_policy = unknown_policy;
+        }
+        Ok(())
+    }
+
+    /// Starts synthesizing the next queued sentence if idle, returning the
+    /// events for markers reached on the way.
+    fn start_next(&mut self) -> Vec<ServerEvent> {
+        let mut events = Vec::new();
+        while self.current.is_none() {
+            match self.queue.pop_front() {
+                Some(Pending::Sentence { text, queued_at }) => {
+                    let lang = self
+                        .config
+                        .language
+                        .clone()
+                        .unwrap_or_else(|| detect_language(&text));
+                    let opts = TTSOpts::new()
+                        .with_speed(self.config.speed)
+                        .with_normalize(self.config.normalize)
+                        .with_unknown_policy(self.config.unknown_policy.clone());
+                    let stream = TTSStream::new(Arc::clone(&self.tts), &text, &lang, &self.config.voice, opts)
+                        .map_err(|e| e.to_string())
+                        .and_then(|stream| self.pool.stream(stream).map_err(|e| e.to_string()));
+                    match stream {
+                        Ok(stream) => {
+                            self.current = Some(Job {
+                                text,
+                                queued_at,
+                                stream,
+                                first_chunk: true,
+                            })
+                        }
+                        Err(message) => events.push(ServerEvent::Error { message }),
+                    }
+                }
+                Some(Pending::Flush) => events.push(ServerEvent::Flushed),
+                None => break,
+            }
+        }
+        events
+    }
+
+    /// Waits for the next chunk of the sentence in progress, forever when
+    /// idle so it can sit in a `select!`.
+    async fn next_chunk(&mut self) -> Option<Result<AudioChunk, TtsError>> {
+        match self.current.as_mut() {
+            Some(job) => job.stream.next().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Turns a synthesized chunk into the frames to send.
+    fn chunk_frames(&mut self, chunk: AudioChunk) -> Result<Vec<Message>, String> {
+        let Some(job) = self.current.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let mut samples = Vec::new();
+        if job.first_chunk && self.audio_sent {
+            // Sentences are synthesized separately, put back the pause between them
+            let silence = ms_to_samples(self.tts.chunk_config().sentence_silence_ms, chunk.sample_rate);
+            samples.resize(silence, 0.0);
+        }
+        samples.extend(chunk.samples);
+        job.first_chunk = false;
+        self.audio_sent = true;
+
+        let id = self.next_chunk_id;
+        self.next_chunk_id += 1;
+        let pcm = StreamEncoder::new(AudioFormat::Pcm, chunk.sample_rate)?.push(&samples)?;
+
+        Ok(vec![
+            event_message(&ServerEvent::ChunkStart {
+                id,
+                text: job.text.clone(),
+            }),
+            Message::Binary(pcm),
+            event_message(&ServerEvent::ChunkEnd {
+                id,
+                samples: samples.len(),
+                audio_ms: samples.len() as u64 * 1000 / chunk.sample_rate as u64,
+                latency_ms: job.queued_at.elapsed().as_millis() as u64,
+            }),
+        ])
+    }
+}
+
+fn event_message(event: &ServerEvent) -> Message {
+    Message::Text(serde_json::to_string(event).expect("events always serialize"))
+}
+
+async fn send_events(sender: &mut WsSender, events: Vec<ServerEvent>) -> Result<(), axum::Error> {
+    for event in events {
+        sender.send(event_message(&event)).await?;
+    }
+    Ok(())
+}
+
+/// Upgrades to a WebSocket taking text fragments in and sending 16-bit PCM
+/// audio out, sentence by sentence.
+pub async fn speech_ws(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    ws.on_upgrade(move |socket| handle_socket(socket, state.tts, state.pool))
+}
+
+async fn handle_socket(socket: WebSocket, tts: Arc<TTSKoko>, pool: Arc<WorkerPool>) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut session = WsSession::new(tts, pool);
+
+    let ready = ServerEvent::Ready {
+        sample_rate: TTSKoko::SAMPLE_RATE,
+        format: "pcm_s16le",
+    };
+    if send_events(&mut sender, vec![ready]).await.is_err() {
+        return;
+    }
+
+    loop {
+        let events = session.start_next();
+        if send_events(&mut sender, events).await.is_err() {
+            return;
+        }
+
+        tokio::select! {
+            message = receiver.next() => {
+                let events = match message {
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientMessage>(&text) {
+                        Ok(message) => session.handle(message),
+                        Err(e) => vec![ServerEvent::Error { message: format!("invalid message: {}", e) }],
+                    },
+                    Some(Ok(Message::Binary(_))) => vec![ServerEvent::Error {
+                        message: "binary messages are not supported, send JSON text messages".to_string(),
+                    }],
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => Vec::new(),
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                };
+                if send_events(&mut sender, events).await.is_err() {
+                    return;
+                }
+            }
+            chunk = session.next_chunk() => {
+                let frames = match chunk {
+                    Some(Ok(chunk)) => session.chunk_frames(chunk),
+                    Some(Err(e)) => {
+                        session.current = None;
+                        Err(e.to_string())
+                    }
+                    None => {
+                        session.current = None;
+                        Ok(Vec::new())
+                    }
+                };
+                let frames = frames.unwrap_or_else(|message| vec![event_message(&ServerEvent::Error { message })]);
+                for frame in frames {
+                    if sender.send(frame).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serve::pool::PoolConfig;
+
+    /// A session speaking with the synthetic voice "a", on a model that is
+    /// never run: sentences are started but produce no audio.
+    fn session() -> WsSession {
+        let pool = WorkerPool::new(PoolConfig::new().with_workers(1));
+        let mut session = WsSession::new(Arc::new(TTSKoko::synthetic()), Arc::new(pool));
+        session.config.voice = "a".to_string();
+        session
+    }
+
+    fn text(text: &str) -> ClientMessage {
+        ClientMessage::Text { text: text.to_string() }
+    }
+
+    /// The queue as sentences, with flushes as `<flush>`.
+    fn queued(session: &WsSession) -> Vec<String> {
+        session
+            .queue
+            .iter()
+            .map(|pending| match pending {
+                Pending::Sentence { text, .. } => text.clone(),
+                Pending::Flush => "<flush>".to_string(),
+            })
+            .collect()
+    }
+
+    /// Lets the sentence in progress finish and starts the next one.
+    fn finish_current(session: &mut WsSession) -> Vec<ServerEvent> {
+        session.current = None;
+        session.start_next()
+    }
+
+    #[test]
+    fn test_text_is_buffered_to_sentence_boundaries() {
+        let mut session = session();
+        assert!(session.handle(text("Hello there")).is_empty());
+        assert!(queued(&session).is_empty());
+
+        session.handle(text(". How are"));
+        assert_eq!(queued(&session), ["Hello there."]);
+
+        // A sentence ending the buffer may still go on
+        session.handle(text(" you?"));
+        assert_eq!(queued(&session), ["Hello there."]);
+        assert_eq!(session.buffer.trim(), "How are you?");
+
+        session.handle(text(" Fine"));
+        assert_eq!(queued(&session), ["Hello there.", "How are you?"]);
+        assert_eq!(session.buffer.trim(), "Fine");
+    }
+
+    #[test]
+    fn test_long_text_without_boundary_is_cut() {
+        let mut session = session();
+        let word = "word ";
+        let words = MAX_INPUT_CHARS / word.len();
+        session.handle(text(&word.repeat(words)));
+        assert!(queued(&session).is_empty());
+
+        session.handle(text(&word.repeat(2)));
+        assert!(session.buffer.is_empty());
+        let queued = queued(&session).concat();
+        assert_eq!(queued.split_whitespace().count(), words + 2);
+    }
+
+    #[test]
+    fn test_flush_comes_after_queued_sentences() {
+        let mut session = session();
+        session.handle(text("One. Two. Three"));
+        assert!(session.handle(ClientMessage::Flush).is_empty());
+        assert_eq!(queued(&session), ["One.", "Two.", "Three", "<flush>"]);
+
+        for sentence in ["One.", "Two.", "Three"] {
+            let events = finish_current(&mut session);
+            assert!(events.is_empty(), "{:?}", events);
+            assert_eq!(session.current.as_ref().map(|job| job.text.as_str()), Some(sentence));
+        }
+        let events = finish_current(&mut session);
+        assert!(matches!(events[..], [ServerEvent::Flushed]), "{:?}", events);
+        assert!(session.current.is_none());
+    }
+
+    #[test]
+    fn test_cancel_clears_the_queue() {
+        let mut session = session();
+        session.handle(text("One. Two. Three. Four"));
+        session.start_next();
+        assert!(session.current.is_some());
+
+        let events = session.handle(ClientMessage::Cancel);
+        assert!(matches!(events[..], [ServerEvent::Cancelled]));
+        assert!(session.current.is_none());
+        assert!(queued(&session).is_empty());
+        assert!(session.buffer.is_empty());
+        assert!(session.start_next().is_empty());
+        assert!(session.current.is_none());
+    }
+
+    #[test]
+    fn test_config_is_validated_before_applying() {
+        let mut session = session();
+        let config = |voice: &str, language: &str| ClientMessage::Config {
+            voice: Some(voice.to_string()),
+            speed: Some(1.5),
+            language: Some(language.to_string()),
+            normalize: None,
+            unknown_chars: None,
+        };
+
+        let events = session.handle(config("b", "?!"));
+        assert!(matches!(&events[..], [ServerEvent::Error { message }] if message.contains("Unsupported language")));
+        let events = session.handle(config("c", "b"));
+        assert!(matches!(&events[..], [ServerEvent::Error { .. }]), "{:?}", events);
+        assert_eq!(session.config.voice, "a");
+        assert_eq!(session.config.speed, 1.0);
+        assert_eq!(session.config.language, None);
+
+        assert!(session.handle(config("b", "b")).is_empty());
+        assert_eq!(session.config.voice, "b");
+        assert_eq!(session.config.language.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_client_messages() {
+        let message: ClientMessage = serde_json::from_str(r#"{"type": "text", "text": "Hello"}"#).unwrap();
+        assert!(matches!(message, ClientMessage::Text { text } if text == "Hello"));
+
+        let message: ClientMessage = serde_json::from_str(r#"{"type": "config", "speed": 1.2}"#).unwrap();
+        assert!(matches!(message, ClientMessage::Config { speed: Some(s), voice: None, .. } if s == 1.2));
+
+        assert!(matches!(
+            serde_json::from_str(r#"{"type": "flush"}"#).unwrap(),
+            ClientMessage::Flush
+        ));
+        assert!(serde_json::from_str::<ClientMessage>(r#"{"type": "shout"}"#).is_err());
+    }
+
+    #[test]
+    fn test_server_event_shape() {
+        let event = serde_json::to_value(ServerEvent::ChunkEnd {
+            id: 3,
+            samples: 2400,
+            audio_ms: 100,
+            latency_ms: 250,
+        })
+        .unwrap();
+        assert_eq!(
+            event,
+            serde_json::json!({"type": "chunk_end", "id": 3, "samples": 2400, "audio_ms": 100, "latency_ms": 250})
+        );
+    }
+}
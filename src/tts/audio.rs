@@ -0,0 +1,232 @@
+use std::path::Path;
+
+use crate::tts::encode::AudioFormat;
+use crate::tts::timestamps::{SentenceTiming, WordTiming};
+use crate::tts::tokenize::UnknownChar;
+
+/// Mono audio produced by a synthesis run, together with its run stats.
+#[derive(Debug, Clone)]
+pub struct AudioBuffer {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    /// Audio length in seconds.
+    pub duration: f32,
+    /// Number of phonemes that were synthesized.
+    pub phonemes_len: usize,
+    /// Synthesis time divided by audio length, below 1.0 is faster than real-time.
+    pub real_time_factor: f32,
+    /// Phoneme characters the vocab could not tokenize.
+    pub unknown: Vec<UnknownChar>,
+    /// Word and phoneme timings, filled when synthesized with
+    /// `TTSOpts::with_timestamps`.
+    pub words: Vec<WordTiming>,
+    /// Start and end of every synthesized sentence.
+    pub sentences: Vec<SentenceTiming>,
+}
+
+impl AudioBuffer {
+    pub fn new(samples: Vec<f32>, sample_rate: u32, phonemes_len: usize, elapsed: f32) -> Self {
+        let duration = samples.len() as f32 / sample_rate as f32;
+        let real_time_factor = if duration > 0.0 { elapsed / duration } else { 0.0 };
+        AudioBuffer {
+            samples,
+            sample_rate,
+            duration,
+            phonemes_len,
+            real_time_factor,
+            unknown: Vec::new(),
+            words: Vec::new(),
+            sentences: Vec::new(),
+        }
+    }
+
+    /// Encodes the samples in `format`.
+    pub fn encode(&self, format: AudioFormat) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        format.encode(&self.samples, self.sample_rate)
+    }
+
+    /// Writes the samples to `path` in `format`, creating parent directories
+    /// as needed.
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: AudioFormat) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, self.encode(format)?)?;
+        Ok(())
+    }
+}
+
+/// Appends `next` to `out`, separated by `silence` samples of silence.
+///
+/// Without silence the two sides are crossfaded over `crossfade` samples.
+/// With silence, the same length is used to fade out of `out` and into
+/// `next` so the cut to silence does not click.
+pub fn join_samples(out: &mut Vec<f32>, next: &[f32], silence: usize, crossfade: usize) {
+    if out.is_empty() {
+        out.extend_from_slice(next);
+        return;
+    }
+
+    if silence > 0 {
+        let fade_out = crossfade.min(out.len());
+        let tail_start = out.len() - fade_out;
+        for (i, sample) in out[tail_start..].iter_mut().enumerate() {
+            *sample *= 1.0 - (i + 1) as f32 / fade_out as f32;
+        }
+
+        out.resize(out.len() + silence, 0.0);
+
+        let fade_in = crossfade.min(next.len());
+        out.extend(next.iter().enumerate().map(|(i, &sample)| {
+            if i < fade_in {
+                sample * i as f32 / fade_in as f32
+            } else {
+                sample
+            }
+        }));
+        return;
+    }
+
+    let overlap = crossfade.min(out.len()).min(next.len());
+    let tail_start = out.len() - overlap;
+    for i in 0..overlap {
+        let t = (i + 1) as f32 / (overlap + 1) as f32;
+        out[tail_start + i] = out[tail_start + i] * (1.0 - t) + next[i] * t;
+    }
+    out.extend_from_slice(&next[overlap..]);
+}
+
+/// Incremental `join_samples`, for audio that is played as it is produced.
+///
+/// The last `crossfade` samples of each chunk are held back until the next
+/// chunk arrives, so they can still be faded out or crossfaded.
+#[derive(Debug, Clone)]
+pub struct StreamJoiner {
+    pending: Vec<f32>,
+    silence: usize,
+    crossfade: usize,
+    started: bool,
+    emitted: usize,
+}
+
+impl StreamJoiner {
+    pub fn new(silence: usize, crossfade: usize) -> Self {
+        StreamJoiner {
+            pending: Vec::new(),
+            silence,
+            crossfade,
+            started: false,
+            emitted: 0,
+        }
+    }
+
+    /// Joins `next`, after a silence if the previous chunk ended a sentence,
+    /// and returns the samples that will not change anymore.
+    pub fn push(&mut self, next: &[f32], after_sentence: bool) -> Vec<f32> {
+        let gap = if after_sentence { self.silence } else { 0 };
+        if self.pending.is_empty() {
+            // Nothing held back to fade, either the first chunk or no crossfade
+            if self.started {
+                self.pending.resize(gap, 0.0);
+            }
+            self.pending.extend_from_slice(next);
+        } else {
+            join_samples(&mut self.pending, next, gap, self.crossfade);
+        }
+        self.started = true;
+
+        let ready = self.pending.len().saturating_sub(self.crossfade);
+        self.emitted += ready;
+        self.pending.drain(..ready).collect()
+    }
+
+    /// Length of the joined audio so far, including the held back tail. The
+    /// last pushed chunk ends there.
+    pub fn joined_len(&self) -> usize {
+        self.emitted + self.pending.len()
+    }
+
+    /// Returns the held back tail once there are no more chunks.
+    pub fn finish(&mut self) -> Vec<f32> {
+        self.emitted += self.pending.len();
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Converts a duration in milliseconds to a number of samples.
+pub fn ms_to_samples(ms: u32, sample_rate: u32) -> usize {
+    (ms as u64 * sample_rate as u64 / 1000) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_into_empty() {
+        let mut out = Vec::new();
+        join_samples(&mut out, &[0.5; 4], 3, 2);
+        assert_eq!(out, vec![0.5; 4]);
+    }
+
+    #[test]
+    fn test_join_with_silence() {
+        let mut out = vec![1.0; 4];
+        join_samples(&mut out, &[1.0; 4], 3, 2);
+        assert_eq!(out, vec![1.0, 1.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.5, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_join_with_crossfade() {
+        let mut out = vec![1.0; 4];
+        join_samples(&mut out, &[0.0; 4], 0, 3);
+        assert_eq!(out.len(), 5);
+        assert_eq!(out[..1], [1.0]);
+        assert!(out[1] > out[2] && out[2] > out[3] && out[3] > 0.0);
+        assert_eq!(out[4], 0.0);
+    }
+
+    #[test]
+    fn test_crossfade_longer_than_inputs() {
+        let mut out = vec![1.0; 2];
+        join_samples(&mut out, &[1.0; 1], 0, 100);
+        assert_eq!(out.len(), 2);
+        assert!(out.iter().all(|&s| (s - 1.0).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_stream_joiner_matches_join_samples() {
+        let chunks: Vec<Vec<f32>> = vec![vec![1.0; 7], vec![0.5; 3], vec![-1.0; 12], vec![0.25; 5]];
+        let ends_sentence = [false, true, false, true];
+        for (silence, crossfade) in [(0, 0), (4, 0), (0, 4), (3, 5)] {
+            let mut joined = Vec::new();
+            let mut after_sentence = false;
+            for (chunk, &ends) in chunks.iter().zip(&ends_sentence) {
+                let gap = if after_sentence { silence } else { 0 };
+                join_samples(&mut joined, chunk, gap, crossfade);
+                after_sentence = ends;
+            }
+
+            let mut joiner = StreamJoiner::new(silence, crossfade);
+            let mut streamed = Vec::new();
+            let mut after_sentence = false;
+            for (chunk, &ends) in chunks.iter().zip(&ends_sentence) {
+                streamed.extend(joiner.push(chunk, after_sentence));
+                assert!(joiner.joined_len() >= chunk.len());
+                after_sentence = ends;
+            }
+            streamed.extend(joiner.finish());
+            assert_eq!(joiner.joined_len(), joined.len());
+
+            assert_eq!(streamed, joined, "silence {silence}, crossfade {crossfade}");
+        }
+    }
+
+    #[test]
+    fn test_ms_to_samples() {
+        assert_eq!(ms_to_samples(100, 24000), 2400);
+        assert_eq!(ms_to_samples(0, 24000), 0);
+    }
+}
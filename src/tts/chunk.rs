@@ -0,0 +1,371 @@
+use crate::tts::tokenize::tokenize;
+use crate::tts::vocab::PUNCTUATION;
+
+/// Longest phoneme sequence the model accepts, excluding the two `$` pads.
+pub const MAX_PHONEME_TOKENS: usize = 510;
+
+/// Punctuation from the vocab that ends a sentence.
+const SENTENCE_ENDINGS: &[char] = &['.', '!', '?', '…'];
+/// Punctuation from the vocab that ends a clause within a sentence.
+const CLAUSE_ENDINGS: &[char] = &[',', ';', ':', '—'];
+/// Closing marks that stay attached to the sentence they end.
+const CLOSING_MARKS: &[char] = &['"', '»', '”'];
+
+/// Sentence endings in raw text, full-width ones end a sentence without a
+/// following space.
+const TEXT_SENTENCE_ENDINGS: &[char] = &['.', '!', '?', '…', '。', '！', '？'];
+const FULL_WIDTH_ENDINGS: &[char] = &['。', '！', '？'];
+/// Closing marks in raw text, see `CLOSING_MARKS`.
+const TEXT_CLOSING_MARKS: &[char] = &['"', '\'', '»', '”', '’', ')', '」', '』'];
+/// Abbreviations whose period does not end a sentence.
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "st", "jr", "sr", "prof", "vs", "etc", "e.g", "i.e", "no",
+];
+
+#[derive(Debug, Clone)]
+pub struct ChunkConfig {
+    /// Maximum number of phoneme tokens per chunk.
+    pub max_tokens: usize,
+    /// Silence inserted between sentences, in milliseconds.
+    pub sentence_silence_ms: u32,
+    /// Crossfade applied where chunks are joined, in milliseconds.
+    pub crossfade_ms: u32,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: MAX_PHONEME_TOKENS,
+            sentence_silence_ms: 100,
+            crossfade_ms: 10,
+        }
+    }
+}
+
+impl ChunkConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sentence_silence_ms(mut self, ms: u32) -> Self {
+        self.sentence_silence_ms = ms;
+        self
+    }
+
+    pub fn with_crossfade_ms(mut self, ms: u32) -> Self {
+        self.crossfade_ms = ms;
+        self
+    }
+}
+
+/// A piece of phonemized text small enough for a single inference call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhonemeChunk {
+    pub phonemes: String,
+    /// Whether the chunk closes a sentence, as opposed to being cut mid-sentence.
+    pub ends_sentence: bool,
+}
+
+/// Splits a phoneme string into chunks of at most `max_tokens` tokens.
+///
+/// Every sentence gets its own chunk. Sentences that are still too long are
+/// cut at clause punctuation, then at word boundaries, and as a last resort
+/// between characters.
+pub fn split_phonemes(phonemes: &str, max_tokens: usize) -> Vec<PhonemeChunk> {
+    let max_tokens = max_tokens.max(1);
+    let mut chunks = Vec::new();
+
+    for sentence in split_sentences(phonemes) {
+        let pieces = split_to_fit(sentence, max_tokens, 0);
+        let last = pieces.len().saturating_sub(1);
+        for (i, piece) in pieces.into_iter().enumerate() {
+            chunks.push(PhonemeChunk {
+                phonemes: piece,
+                ends_sentence: i == last,
+            });
+        }
+    }
+
+    chunks
+}
+
+/// Splits raw text into sentences, so they can be phonemized and
+/// synthesized one at a time.
+///
+/// A sentence ends at `.`, `!`, `?` or `…` (plus closing quotes) followed by
+/// whitespace and a word that does not start lowercase, unless the period
+/// belongs to a known abbreviation or a single-letter initial. Full-width
+/// endings always end a sentence.
+pub fn split_text_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    for end in sentence_ends(text) {
+        sentences.push(&text[start..end]);
+        start = end;
+    }
+    sentences.push(&text[start..]);
+
+    sentences
+        .into_iter()
+        .map(str::trim)
+        .filter(|s| s.chars().any(char::is_alphanumeric))
+        .collect()
+}
+
+/// Length of the prefix of `text` made of finished sentences, for text that
+/// is still being written.
+///
+/// A sentence ending at the very end of `text` is not counted, the text
+/// that follows could still reveal an abbreviation or a lowercase word.
+pub fn complete_sentences_len(text: &str) -> usize {
+    sentence_ends(text)
+        .into_iter()
+        .rev()
+        .find(|&end| !text[end..].trim().is_empty())
+        .unwrap_or(0)
+}
+
+/// Byte offsets right after each sentence ending in `text`, see
+/// `split_text_sentences`.
+pub(crate) fn sentence_ends(text: &str) -> Vec<usize> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut ends = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (_, c) = chars[i];
+        if !TEXT_SENTENCE_ENDINGS.contains(&c) {
+            i += 1;
+            continue;
+        }
+
+        let mut end = i + 1;
+        while end < chars.len()
+            && (TEXT_SENTENCE_ENDINGS.contains(&chars[end].1) || TEXT_CLOSING_MARKS.contains(&chars[end].1))
+        {
+            end += 1;
+        }
+        let end_byte = chars.get(end).map_or(text.len(), |&(b, _)| b);
+
+        let boundary = if FULL_WIDTH_ENDINGS.contains(&c) {
+            true
+        } else if end < chars.len() && !chars[end].1.is_whitespace() {
+            false
+        } else {
+            let next = chars[end..].iter().map(|&(_, c)| c).find(|c| !c.is_whitespace());
+            let next_is_lower = next.is_some_and(char::is_lowercase);
+            let abbreviation = c == '.' && is_abbreviation(&text[start..chars[i].0]);
+            !next_is_lower && !abbreviation
+        };
+
+        if boundary {
+            ends.push(end_byte);
+            start = end_byte;
+        }
+        i = end;
+    }
+
+    ends
+}
+
+/// Whether the word ending `text` is an abbreviation or a single letter.
+fn is_abbreviation(text: &str) -> bool {
+    let word = text
+        .rsplit(|c: char| c.is_whitespace() || c == '(' || c == '"')
+        .next()
+        .unwrap_or("");
+    let lower = word.to_lowercase();
+    (word.chars().count() == 1 && word.chars().all(char::is_alphabetic))
+        || ABBREVIATIONS.contains(&lower.as_str())
+}
+
+fn token_len(phonemes: &str) -> usize {
+    tokenize(phonemes).len()
+}
+
+/// Splits after each sentence ending, keeping trailing punctuation and
+/// closing marks with the sentence.
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut in_ending = false;
+
+    for (i, c) in text.char_indices() {
+        if SENTENCE_ENDINGS.contains(&c) || (in_ending && CLOSING_MARKS.contains(&c)) {
+            in_ending = true;
+        } else if in_ending {
+            sentences.push(&text[start..i]);
+            start = i;
+            in_ending = false;
+        }
+    }
+    sentences.push(&text[start..]);
+
+    sentences
+        .into_iter()
+        .map(str::trim)
+        .filter(|s| token_len(s) > 0 && !s.chars().all(|c| PUNCTUATION.contains(c)))
+        .collect()
+}
+
+/// Splits `text` right after every character matching `is_delim`.
+fn split_after(text: &str, is_delim: impl Fn(char) -> bool) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if is_delim(c) {
+            let end = i + c.len_utf8();
+            pieces.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() {
+        pieces.push(&text[start..]);
+    }
+    pieces
+}
+
+/// Recursively splits `text` until every piece fits in `max_tokens`, trying
+/// clause punctuation first (`level` 0), then spaces (`level` 1).
+fn split_to_fit(text: &str, max_tokens: usize, level: usize) -> Vec<String> {
+    if token_len(text) <= max_tokens {
+        return vec![text.trim().to_string()];
+    }
+
+    let pieces = match level {
+        0 => split_after(text, |c| CLAUSE_ENDINGS.contains(&c)),
+        1 => split_after(text, |c| c == ' '),
+        _ => return split_chars(text, max_tokens),
+    };
+
+    let pieces: Vec<String> = pieces
+        .into_iter()
+        .flat_map(|piece| split_to_fit(piece, max_tokens, level + 1))
+        .collect();
+
+    pack(pieces, max_tokens)
+}
+
+/// Hard split for runs without any usable boundary.
+fn split_chars(text: &str, max_tokens: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    for c in text.trim().chars() {
+        if token_len(&current) + token_len(c.encode_utf8(&mut [0; 4])) > max_tokens {
+            pieces.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Greedily merges consecutive trimmed pieces, separated by a space, as long
+/// as they stay within `max_tokens`.
+fn pack(pieces: Vec<String>, max_tokens: usize) -> Vec<String> {
+    let mut packed: Vec<String> = Vec::new();
+    for piece in pieces.into_iter().filter(|p| !p.is_empty()) {
+        match packed.last_mut() {
+            Some(last) if token_len(last) + 1 + token_len(&piece) <= max_tokens => {
+                last.push(' ');
+                last.push_str(&piece);
+            }
+            _ => packed.push(piece),
+        }
+    }
+    packed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundaries_are_vocab_punctuation() {
+        for c in SENTENCE_ENDINGS.iter().chain(CLAUSE_ENDINGS).chain(CLOSING_MARKS) {
+            assert!(PUNCTUATION.contains(*c), "{c:?} is not in the vocab");
+        }
+    }
+
+    #[test]
+    fn test_one_chunk_per_sentence() {
+        let chunks = split_phonemes("hˈɛloʊ. haʊ ɑːɹ juː? ɡˈʊd!", MAX_PHONEME_TOKENS);
+        let phonemes: Vec<&str> = chunks.iter().map(|c| c.phonemes.as_str()).collect();
+        assert_eq!(phonemes, vec!["hˈɛloʊ.", "haʊ ɑːɹ juː?", "ɡˈʊd!"]);
+        assert!(chunks.iter().all(|c| c.ends_sentence));
+    }
+
+    #[test]
+    fn test_closing_quote_stays_with_sentence() {
+        let chunks = split_phonemes("\"hˈɛloʊ!\" ʃiː sˈɛd.", MAX_PHONEME_TOKENS);
+        let phonemes: Vec<&str> = chunks.iter().map(|c| c.phonemes.as_str()).collect();
+        assert_eq!(phonemes, vec!["\"hˈɛloʊ!\"", "ʃiː sˈɛd."]);
+    }
+
+    #[test]
+    fn test_long_sentence_splits_at_clauses() {
+        let chunks = split_phonemes("aaaa, bbbb, cccc.", 12);
+        let phonemes: Vec<&str> = chunks.iter().map(|c| c.phonemes.as_str()).collect();
+        assert_eq!(phonemes, vec!["aaaa, bbbb,", "cccc."]);
+        assert!(!chunks[0].ends_sentence);
+        assert!(chunks[1].ends_sentence);
+    }
+
+    #[test]
+    fn test_long_clause_splits_at_words() {
+        let chunks = split_phonemes("aaa bbb ccc ddd", 8);
+        let phonemes: Vec<&str> = chunks.iter().map(|c| c.phonemes.as_str()).collect();
+        assert_eq!(phonemes, vec!["aaa bbb", "ccc ddd"]);
+    }
+
+    #[test]
+    fn test_chunks_never_exceed_limit() {
+        let text = "a".repeat(25) + " " + &"b, ".repeat(40) + "c.";
+        for max in [1, 3, 7, 16] {
+            let chunks = split_phonemes(&text, max);
+            assert!(!chunks.is_empty());
+            for chunk in &chunks {
+                assert!(token_len(&chunk.phonemes) <= max, "{chunk:?} exceeds {max}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_text_sentences() {
+        let text = "Hello there! Dr. Smith paid $3.50 at 5 p.m. today. \"Really?\" she asked. U.S.A. is big… ok";
+        assert_eq!(
+            split_text_sentences(text),
+            vec![
+                "Hello there!",
+                "Dr. Smith paid $3.50 at 5 p.m. today.",
+                "\"Really?\" she asked.",
+                "U.S.A. is big… ok",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_text_sentences_full_width() {
+        assert_eq!(split_text_sentences("你好。今天天气很好！"), vec!["你好。", "今天天气很好！"]);
+        assert!(split_text_sentences(" ... ").is_empty());
+    }
+
+    #[test]
+    fn test_complete_sentences_len() {
+        assert_eq!(complete_sentences_len("Hello there"), 0);
+        // The next fragment could still be lowercase
+        assert_eq!(complete_sentences_len("Hello there. "), 0);
+        assert_eq!(complete_sentences_len("Hello there. How"), "Hello there.".len());
+        assert_eq!(complete_sentences_len("Hello there. I met Dr. "), "Hello there.".len());
+        assert_eq!(complete_sentences_len("你好。今天"), "你好。".len());
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert!(split_phonemes("", MAX_PHONEME_TOKENS).is_empty());
+        assert!(split_phonemes("  . ", MAX_PHONEME_TOKENS).is_empty());
+    }
+}
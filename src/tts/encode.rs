@@ -0,0 +1,584 @@
+use std::io::{Cursor, Seek, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use flacenc::component::BitRepr;
+use flacenc::error::Verify;
+
+/// Output encodings, named after the OpenAI `response_format` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AudioFormat {
+    /// 16-bit PCM WAV.
+    #[default]
+    Wav,
+    /// Raw 16-bit little-endian mono samples, without a header.
+    Pcm,
+    Flac,
+    Mp3,
+    /// Opus in an Ogg container.
+    Opus,
+}
+
+const FORMAT_NAMES: [&str; 5] = ["wav", "pcm", "flac", "mp3", "opus"];
+
+/// Bits per sample used by the integer encodings.
+const BITS_PER_SAMPLE: u16 = 16;
+
+const MP3_BITRATE: mp3lame_encoder::Bitrate = mp3lame_encoder::Bitrate::Kbps64;
+
+/// Opus frame length in milliseconds.
+const OPUS_FRAME_MS: u32 = 20;
+/// Largest packet size recommended by the Opus documentation.
+const OPUS_MAX_PACKET: usize = 4000;
+/// Ogg granule positions are always counted at 48 kHz for Opus.
+const OPUS_GRANULE_RATE: u32 = 48000;
+const OGG_SERIAL: u32 = 0x4b6f6b6f;
+
+impl AudioFormat {
+    /// Guesses the format from the extension of `path`.
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "wav" => Some(AudioFormat::Wav),
+            "pcm" | "raw" => Some(AudioFormat::Pcm),
+            "flac" => Some(AudioFormat::Flac),
+            "mp3" => Some(AudioFormat::Mp3),
+            "opus" | "ogg" => Some(AudioFormat::Opus),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::Pcm => "pcm",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Opus => "opus",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "audio/wav",
+            AudioFormat::Pcm => "audio/pcm",
+            AudioFormat::Flac => "audio/flac",
+            AudioFormat::Mp3 => "audio/mpeg",
+            AudioFormat::Opus => "audio/ogg",
+        }
+    }
+
+    /// Whether the format can be written chunk by chunk, see `StreamEncoder`.
+    pub fn is_streamable(&self) -> bool {
+        matches!(self, AudioFormat::Wav | AudioFormat::Pcm | AudioFormat::Opus)
+    }
+
+    /// Encodes mono samples in the -1.0..=1.0 range.
+    pub fn encode(&self, samples: &[f32], sample_rate: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match self {
+            AudioFormat::Wav => {
+                let mut cursor = Cursor::new(Vec::new());
+                write_wav(&mut cursor, samples, sample_rate)?;
+                Ok(cursor.into_inner())
+            }
+            AudioFormat::Pcm => Ok(to_i16(samples).flat_map(i16::to_le_bytes).collect()),
+            AudioFormat::Flac => encode_flac(samples, sample_rate),
+            AudioFormat::Mp3 => encode_mp3(samples, sample_rate),
+            AudioFormat::Opus => encode_opus(samples, sample_rate),
+        }
+    }
+}
+
+impl FromStr for AudioFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wav" => Ok(AudioFormat::Wav),
+            "pcm" => Ok(AudioFormat::Pcm),
+            "flac" => Ok(AudioFormat::Flac),
+            "mp3" => Ok(AudioFormat::Mp3),
+            "opus" | "ogg" => Ok(AudioFormat::Opus),
+            "aac" => Err(format!(
+                "aac output is not supported, expected one of: {}",
+                FORMAT_NAMES.join(", ")
+            )),
+            other => Err(format!(
+                "unknown audio format '{}', expected one of: {}",
+                other,
+                FORMAT_NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for AudioFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn to_i16(samples: &[f32]) -> impl Iterator<Item = i16> + '_ {
+    samples
+        .iter()
+        .map(|&s| (s.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16)
+}
+
+fn write_wav<W: Write + Seek>(writer: W, samples: &[f32], sample_rate: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: BITS_PER_SAMPLE,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut writer = hound::WavWriter::new(writer, spec)?;
+    for sample in to_i16(samples) {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+fn encode_flac(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, e)| format!("invalid FLAC encoder config: {:?}", e))?;
+    let samples: Vec<i32> = to_i16(samples).map(i32::from).collect();
+    let source = flacenc::source::MemSource::from_samples(
+        &samples,
+        1,
+        BITS_PER_SAMPLE as usize,
+        sample_rate as usize,
+    );
+    let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|e| format!("FLAC encoding failed: {:?}", e))?;
+
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|e| format!("FLAC encoding failed: {:?}", e))?;
+    Ok(sink.as_slice().to_vec())
+}
+
+fn encode_mp3(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    use mp3lame_encoder::{Builder, FlushNoGap, MonoPcm, Quality};
+
+    let mut encoder = Builder::new()
+        .ok_or("failed to create MP3 encoder")?
+        .with_num_channels(1)?
+        .with_sample_rate(sample_rate)?
+        .with_brate(MP3_BITRATE)?
+        .with_quality(Quality::Best)?
+        .build()?;
+
+    let samples: Vec<i16> = to_i16(samples).collect();
+    let mut out = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(samples.len()));
+    encoder.encode_to_vec(MonoPcm(&samples), &mut out)?;
+    // lame needs up to 7200 bytes to flush its last frames
+    out.reserve(7200);
+    encoder.flush_to_vec::<FlushNoGap>(&mut out)?;
+    Ok(out)
+}
+
+/// Owns a libopus encoder so it is freed on every return path.
+struct OpusEncoder(*mut unsafe_libopus::OpusEncoder);
+
+impl OpusEncoder {
+    fn new(sample_rate: u32) -> Result<Self, String> {
+        let mut error = 0;
+        // SAFETY: the returned pointer is checked before use and freed in Drop
+        let st = unsafe {
+            unsafe_libopus::opus_encoder_create(
+                sample_rate as i32,
+                1,
+                unsafe_libopus::OPUS_APPLICATION_AUDIO,
+                &mut error,
+            )
+        };
+        if st.is_null() || error != unsafe_libopus::OPUS_OK {
+            return Err(format!(
+                "failed to create Opus encoder at {} Hz (error {}), supported rates are 8000, 12000, 16000, 24000 and 48000",
+                sample_rate, error
+            ));
+        }
+        Ok(OpusEncoder(st))
+    }
+
+    /// Samples the encoder delays its output by, at the input rate.
+    fn lookahead(&mut self) -> Result<u32, String> {
+        let mut lookahead = 0i32;
+        // SAFETY: self.0 is a live encoder and the request takes one `&mut i32`
+        let ret = unsafe {
+            unsafe_libopus::opus_encoder_ctl!(
+                self.0,
+                unsafe_libopus::OPUS_GET_LOOKAHEAD_REQUEST,
+                &mut lookahead
+            )
+        };
+        if ret != unsafe_libopus::OPUS_OK {
+            return Err(format!("failed to read Opus lookahead (error {})", ret));
+        }
+        Ok(lookahead as u32)
+    }
+
+    fn encode_frame(&mut self, frame: &[f32], packet: &mut [u8]) -> Result<usize, String> {
+        // SAFETY: both buffers outlive the call and their lengths are passed along
+        let len = unsafe {
+            unsafe_libopus::opus_encode_float(
+                self.0,
+                frame.as_ptr(),
+                frame.len() as i32,
+                packet.as_mut_ptr(),
+                packet.len() as i32,
+            )
+        };
+        if len < 0 {
+            return Err(format!("Opus encoding failed (error {})", len));
+        }
+        Ok(len as usize)
+    }
+}
+
+impl Drop for OpusEncoder {
+    fn drop(&mut self) {
+        // SAFETY: self.0 came from opus_encoder_create and is freed only here
+        unsafe { unsafe_libopus::opus_encoder_destroy(self.0) }
+    }
+}
+
+// SAFETY: the encoder state is plain memory owned by this value, it is only
+// ever used through `&mut self`
+unsafe impl Send for OpusEncoder {}
+
+/// Incremental Ogg Opus writer, following RFC 7845.
+///
+/// Samples are encoded in 20 ms frames as they come in, each `push` ends the
+/// Ogg page so the bytes it returns can be played right away.
+pub struct OggOpusEncoder {
+    encoder: OpusEncoder,
+    writer: ogg::writing::PacketWriter<'static, Vec<u8>>,
+    frame_size: usize,
+    granule_scale: u64,
+    lookahead: usize,
+    pre_skip: u64,
+    /// Samples waiting for a full frame.
+    buffer: Vec<f32>,
+    samples_in: u64,
+    frames_out: u64,
+}
+
+impl OggOpusEncoder {
+    pub fn new(sample_rate: u32) -> Result<Self, String> {
+        use ogg::writing::PacketWriteEndInfo;
+
+        let mut encoder = OpusEncoder::new(sample_rate)?;
+        let granule_scale = (OPUS_GRANULE_RATE / sample_rate) as u64;
+        let lookahead = encoder.lookahead()?;
+        let pre_skip = lookahead as u64 * granule_scale;
+
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // version
+        head.push(1); // channels
+        head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+        head.extend_from_slice(&sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+
+        let vendor = concat!("koko ", env!("CARGO_PKG_VERSION"));
+        let mut tags = Vec::new();
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes()); // no user comments
+
+        let mut writer = ogg::writing::PacketWriter::new(Vec::new());
+        writer
+            .write_packet(head, OGG_SERIAL, PacketWriteEndInfo::EndPage, 0)
+            .and_then(|_| writer.write_packet(tags, OGG_SERIAL, PacketWriteEndInfo::EndPage, 0))
+            .map_err(|e| e.to_string())?;
+
+        Ok(OggOpusEncoder {
+            encoder,
+            writer,
+            frame_size: (sample_rate * OPUS_FRAME_MS / 1000) as usize,
+            granule_scale,
+            lookahead: lookahead as usize,
+            pre_skip,
+            buffer: Vec::new(),
+            samples_in: 0,
+            frames_out: 0,
+        })
+    }
+
+    /// Encodes every full frame available and returns the Ogg bytes written
+    /// so far, the stream headers included on the first call.
+    pub fn push(&mut self, samples: &[f32]) -> Result<Vec<u8>, String> {
+        self.samples_in += samples.len() as u64;
+        self.buffer.extend_from_slice(samples);
+
+        let full = self.buffer.len() / self.frame_size * self.frame_size;
+        let frames: Vec<f32> = self.buffer.drain(..full).collect();
+        self.write_frames(&frames, false)?;
+        Ok(std::mem::take(self.writer.inner_mut()))
+    }
+
+    /// Pads and encodes the remaining samples, flushing the encoder
+    /// lookahead, and returns the last Ogg page.
+    pub fn finish(&mut self) -> Result<Vec<u8>, String> {
+        // Pad with silence so the lookahead is flushed and the last frame is full
+        let mut frames = std::mem::take(&mut self.buffer);
+        let len = (frames.len() + self.lookahead).max(1).div_ceil(self.frame_size) * self.frame_size;
+        frames.resize(len, 0.0);
+        self.write_frames(&frames, true)?;
+        Ok(std::mem::take(self.writer.inner_mut()))
+    }
+
+    fn write_frames(&mut self, samples: &[f32], end_stream: bool) -> Result<(), String> {
+        use ogg::writing::PacketWriteEndInfo;
+
+        let frame_count = samples.len() / self.frame_size;
+        let mut packet = vec![0u8; OPUS_MAX_PACKET];
+        for (i, frame) in samples.chunks_exact(self.frame_size).enumerate() {
+            let len = self.encoder.encode_frame(frame, &mut packet)?;
+            self.frames_out += 1;
+
+            let last = i + 1 == frame_count;
+            let (end_info, granule) = if last && end_stream {
+                let granule = self.pre_skip + self.samples_in * self.granule_scale;
+                (PacketWriteEndInfo::EndStream, granule)
+            } else {
+                let granule = self.frames_out * self.frame_size as u64 * self.granule_scale;
+                let end_info = if last {
+                    PacketWriteEndInfo::EndPage
+                } else {
+                    PacketWriteEndInfo::NormalPacket
+                };
+                (end_info, granule)
+            };
+            self.writer
+                .write_packet(packet[..len].to_vec(), OGG_SERIAL, end_info, granule)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_opus(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut encoder = OggOpusEncoder::new(sample_rate)?;
+    let mut out = encoder.push(samples)?;
+    out.extend(encoder.finish()?);
+    Ok(out)
+}
+
+/// Encodes audio chunk by chunk for formats that can be played while they
+/// are still being written.
+pub enum StreamEncoder {
+    /// 16-bit WAV whose header leaves the data size unknown.
+    Wav { header: Option<Vec<u8>> },
+    Pcm,
+    Opus(Box<OggOpusEncoder>),
+}
+
+impl StreamEncoder {
+    /// Fails for formats that need the whole audio before writing it.
+    pub fn new(format: AudioFormat, sample_rate: u32) -> Result<Self, String> {
+        match format {
+            AudioFormat::Wav => Ok(StreamEncoder::Wav {
+                header: Some(streaming_wav_header(sample_rate)),
+            }),
+            AudioFormat::Pcm => Ok(StreamEncoder::Pcm),
+            AudioFormat::Opus => Ok(StreamEncoder::Opus(Box::new(OggOpusEncoder::new(sample_rate)?))),
+            AudioFormat::Flac | AudioFormat::Mp3 => {
+                Err(format!("{} output cannot be streamed", format))
+            }
+        }
+    }
+
+    /// Encodes the next samples, returning the bytes ready to be sent.
+    pub fn push(&mut self, samples: &[f32]) -> Result<Vec<u8>, String> {
+        match self {
+            StreamEncoder::Wav { header } => {
+                let mut out = header.take().unwrap_or_default();
+                out.extend(to_i16(samples).flat_map(i16::to_le_bytes));
+                Ok(out)
+            }
+            StreamEncoder::Pcm => Ok(to_i16(samples).flat_map(i16::to_le_bytes).collect()),
+            StreamEncoder::Opus(encoder) => encoder.push(samples),
+        }
+    }
+
+    /// Returns whatever is left to send once all samples were pushed.
+    pub fn finish(&mut self) -> Result<Vec<u8>, String> {
+        match self {
+            StreamEncoder::Wav { header } => Ok(header.take().unwrap_or_default()),
+            StreamEncoder::Pcm => Ok(Vec::new()),
+            StreamEncoder::Opus(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// WAV header for 16-bit mono audio of unknown length, with the RIFF and
+/// data sizes set to their maximum as players expect from a stream. The data
+/// size stays a whole number of samples so strict readers accept it.
+fn streaming_wav_header(sample_rate: u32) -> Vec<u8> {
+    let block_align = BITS_PER_SAMPLE / 8;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // channels
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&(u32::MAX - u32::MAX % block_align as u32).to_le_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (i as f32 * 440.0 * 2.0 * std::f32::consts::PI / 24000.0).sin() * 0.5)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!("wav".parse::<AudioFormat>(), Ok(AudioFormat::Wav));
+        assert_eq!("MP3".parse::<AudioFormat>(), Ok(AudioFormat::Mp3));
+        assert!("aac".parse::<AudioFormat>().unwrap_err().contains("not supported"));
+        assert_eq!("ogg".parse::<AudioFormat>(), Ok(AudioFormat::Opus));
+    }
+
+    #[test]
+    fn test_from_extension() {
+        assert_eq!(AudioFormat::from_extension("tmp/out.flac"), Some(AudioFormat::Flac));
+        assert_eq!(AudioFormat::from_extension("out.OGG"), Some(AudioFormat::Opus));
+        assert_eq!(AudioFormat::from_extension("out.raw"), Some(AudioFormat::Pcm));
+        assert_eq!(AudioFormat::from_extension("out"), None);
+        assert_eq!(AudioFormat::from_extension("out.txt"), None);
+    }
+
+    #[test]
+    fn test_wav_is_16_bit() {
+        let bytes = AudioFormat::Wav.encode(&[0.0, 1.0, -1.0, 2.0], 24000).unwrap();
+        let mut reader = hound::WavReader::new(Cursor::new(bytes)).unwrap();
+        let spec = reader.spec();
+        assert_eq!(spec.bits_per_sample, 16);
+        assert_eq!(spec.sample_format, hound::SampleFormat::Int);
+        assert_eq!(spec.sample_rate, 24000);
+        let samples: Vec<i16> = reader.samples::<i16>().map(Result::unwrap).collect();
+        assert_eq!(samples, vec![0, i16::MAX, -i16::MAX, i16::MAX]);
+    }
+
+    #[test]
+    fn test_pcm_is_raw_little_endian() {
+        let bytes = AudioFormat::Pcm.encode(&[0.0, 1.0, -1.0], 24000).unwrap();
+        assert_eq!(bytes, vec![0x00, 0x00, 0xff, 0x7f, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn test_flac_header() {
+        let bytes = AudioFormat::Flac.encode(&sine(24000), 24000).unwrap();
+        assert_eq!(&bytes[..4], b"fLaC");
+    }
+
+    #[test]
+    fn test_mp3_frames() {
+        let bytes = AudioFormat::Mp3.encode(&sine(24000), 24000).unwrap();
+        assert!(bytes.len() > 1000);
+        // MPEG frame sync
+        assert_eq!(bytes[0], 0xff);
+        assert_eq!(bytes[1] & 0xe0, 0xe0);
+    }
+
+    #[test]
+    fn test_ogg_opus_stream() {
+        let samples = sine(24000);
+        let bytes = AudioFormat::Opus.encode(&samples, 24000).unwrap();
+        assert_eq!(&bytes[..4], b"OggS");
+
+        let mut reader = ogg::PacketReader::new(Cursor::new(bytes));
+        let head = reader.read_packet_expected().unwrap();
+        assert_eq!(&head.data[..8], b"OpusHead");
+        let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]) as u64;
+        assert_eq!(u32::from_le_bytes(head.data[12..16].try_into().unwrap()), 24000);
+
+        let tags = reader.read_packet_expected().unwrap();
+        assert_eq!(&tags.data[..8], b"OpusTags");
+
+        let mut last = None;
+        while let Some(packet) = reader.read_packet().unwrap() {
+            last = Some(packet);
+        }
+        let last = last.unwrap();
+        assert!(last.last_in_stream());
+        assert_eq!(last.absgp_page(), pre_skip + samples.len() as u64 * 2);
+    }
+
+    #[test]
+    fn test_streamed_opus_is_a_valid_stream() {
+        let samples = sine(24000);
+        let mut encoder = StreamEncoder::new(AudioFormat::Opus, 24000).unwrap();
+        let mut bytes = Vec::new();
+        for chunk in samples.chunks(5000) {
+            bytes.extend(encoder.push(chunk).unwrap());
+        }
+        bytes.extend(encoder.finish().unwrap());
+
+        let mut reader = ogg::PacketReader::new(Cursor::new(bytes));
+        let head = reader.read_packet_expected().unwrap();
+        let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]) as u64;
+        reader.read_packet_expected().unwrap();
+
+        let mut granules = Vec::new();
+        let mut last = None;
+        while let Some(packet) = reader.read_packet().unwrap() {
+            if packet.last_in_page() {
+                granules.push(packet.absgp_page());
+            }
+            last = Some(packet);
+        }
+        assert!(last.unwrap().last_in_stream());
+        assert!(granules.windows(2).all(|w| w[0] <= w[1]), "{granules:?}");
+        assert_eq!(*granules.last().unwrap(), pre_skip + samples.len() as u64 * 2);
+        // One page per pushed chunk, plus the final one
+        assert_eq!(granules.len(), 6);
+    }
+
+    #[test]
+    fn test_streamed_wav_header() {
+        let mut encoder = StreamEncoder::new(AudioFormat::Wav, 24000).unwrap();
+        let first = encoder.push(&[0.0, 1.0]).unwrap();
+        assert_eq!(first.len(), 44 + 4);
+        assert_eq!(&first[..4], b"RIFF");
+        assert_eq!(&first[36..40], b"data");
+        assert_eq!(&first[44..], [0x00, 0x00, 0xff, 0x7f]);
+
+        assert_eq!(encoder.push(&[-1.0]).unwrap(), vec![0x01, 0x80]);
+        assert!(encoder.finish().unwrap().is_empty());
+
+        let reader = hound::WavReader::new(Cursor::new(streaming_wav_header(24000))).unwrap();
+        assert_eq!(reader.spec().bits_per_sample, 16);
+        assert_eq!(reader.spec().sample_rate, 24000);
+    }
+
+    #[test]
+    fn test_stream_encoder_rejects_whole_file_formats() {
+        assert!(StreamEncoder::new(AudioFormat::Flac, 24000).is_err());
+        assert!(StreamEncoder::new(AudioFormat::Mp3, 24000).is_err());
+    }
+
+    #[test]
+    fn test_opus_rejects_unsupported_rate() {
+        assert!(AudioFormat::Opus.encode(&[0.0; 100], 22050).is_err());
+    }
+}
@@ -1,239 +1,647 @@
-use crate::tts::tokenize::tokenize;
+use crate::tts::audio::{join_samples, ms_to_samples, AudioBuffer};
+use crate::tts::chunk::{split_phonemes, split_text_sentences, ChunkConfig};
+use crate::tts::phonemizer::Phonemizer;
+use crate::tts::precision::Precision;
+use crate::tts::stream::{AudioStream, ChunkCollector, TTSStream};
+use crate::tts::timestamps::{sentence_timing, WordAligner};
+use crate::tts::tokenize::{tokenize, tokenize_with_policy, UnknownPolicy};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Instant;
 
-use ndarray::{ArrayBase, IxDyn, OwnedRepr};
+use crate::onn::{OrtBase, OrtKoko, OrtConfig, Provider};
+use crate::tts::voices::{has_voice_files, load_voices, StyleTable, SHIPPED_VOICES_DIR};
+use crate::utils::models::{ModelStore, VOICES};
+
+/// Padded tokens, style vector and phoneme token count for one inference.
+type ChunkInputs = (Vec<Vec<i64>>, Vec<Vec<f32>>, usize);
+
+/// Per-call synthesis options.
+#[derive(Debug, Clone)]
+pub struct TTSOpts {
+    /// Speaking rate, within `TTSKoko::MIN_SPEED..=TTSKoko::MAX_SPEED`.
+    pub speed: f32,
+    /// Normalize English text (titles, numbers, acronyms...) before phonemizing.
+    pub normalize: bool,
+    /// How to handle phonemes the vocab cannot tokenize.
+    pub unknown_policy: UnknownPolicy,
+    /// Time every word and phoneme, see `AudioBuffer::words`.
+    pub timestamps: bool,
+}
+
+impl Default for TTSOpts {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            normalize: true,
+            unknown_policy: UnknownPolicy::default(),
+            timestamps: false,
+        }
+    }
+}
+
+impl TTSOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    pub fn with_unknown_policy(mut self, unknown_policy: UnknownPolicy) -> Self {
+        self.unknown_policy = unknown_policy;
+        self
+    }
+
+    pub fn with_timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+}
 
-use crate::onn::{OrtBase, OrtKoko, OrtConfig};
-use crate::utils;
-use crate::utils::fileio::load_json_file;
+/// Why a synthesis failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TtsError {
+    /// What was asked for cannot be synthesized, `param` names the option
+    /// at fault, such as `voice` or `unknown_chars`.
+    InvalidInput { param: &'static str, message: String },
+    /// The model, or anything else along the way, failed.
+    Failed(String),
+}
 
-use espeak_rs::text_to_phonemes;
+impl TtsError {
+    pub fn invalid(param: &'static str, message: impl Into<String>) -> Self {
+        TtsError::InvalidInput {
+            param,
+            message: message.into(),
+        }
+    }
+
+    /// Recovers the `TtsError` of an error returned by `TTSKoko::tts`,
+    /// other errors are failures.
+    pub fn from_boxed(error: Box<dyn std::error::Error>) -> Self {
+        match error.downcast::<TtsError>() {
+            Ok(error) => *error,
+            Err(error) => TtsError::Failed(error.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for TtsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TtsError::InvalidInput { message, .. } | TtsError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for TtsError {}
+
+impl From<String> for TtsError {
+    fn from(message: String) -> Self {
+        TtsError::Failed(message)
+    }
+}
 
 #[derive(Clone)]
 pub struct TTSKoko {
     model_path: String,
     model: Arc<OrtKoko>,
-    styles: HashMap<String, [[[f32; 256]; 1]; 511]>,
+    styles: HashMap<String, StyleTable>,
+    chunk_config: ChunkConfig,
 }
 
 impl TTSKoko {
-    const MODEL_URL: &str =
-        "https://huggingface.co/hexgrad/Kokoro-82M/resolve/main/kokoro-v0_19.onnx";
-    const JSON_DATA_F: &str = "data/voices.json";
 
-    const SAMPLE_RATE: u32 = 24000;
+    pub const SAMPLE_RATE: u32 = 24000;
+
+    /// Speaking-rate bounds, matching the range accepted by the OpenAI API.
+    pub const MIN_SPEED: f32 = 0.25;
+    pub const MAX_SPEED: f32 = 4.0;
 
     pub fn new(model_path: &str) -> Self {
-        Self::with_gpu(model_path, false)
+        Self::load(model_path, None, Precision::Fp32, Self::default_config(false), &ModelStore::default())
+            .expect("Failed to create Kokoro TTS model")
     }
 
-    pub fn with_gpu(model_path: &str, use_gpu: bool) -> Self {
-        let p = Path::new(model_path);
-        if !p.exists() {
-            utils::fileio::download_file_from_url(TTSKoko::MODEL_URL, model_path)
-                .expect("download model failed.");
-        } else {
-            println!("load model from: {}", model_path);
-        }
-
-        let config = if use_gpu {
-            println!("Initializing with GPU configuration...");
+    /// Default model configuration, on GPU or CPU.
+    pub fn default_config(use_gpu: bool) -> OrtConfig {
+        if use_gpu {
             OrtConfig::new()
                 .with_gpu(true)
                 .with_gpu_memory_limit(Some(4 * 1024 * 1024 * 1024))  // 4GB
                 .with_cpu_fallback(true)
         } else {
-            println!("Initializing with CPU configuration...");
             OrtConfig::default()
+        }
+    }
+
+    /// Loads the model at `model_path`, and the voices at
+    /// `voices_path`, a voice file or directory, or else the shipped voices
+    /// in `data/` if there are any, or else those of `store`.
+    ///
+    /// When `model_path` is where `store` keeps the `precision` model, the
+    /// model is downloaded if missing and checked against its checksum.
+    /// Any other path must already exist and is loaded as is.
+    pub fn load(
+        model_path: &str,
+        voices_path: Option<&Path>,
+        precision: Precision,
+        config: OrtConfig,
+        store: &ModelStore,
+    ) -> Result<Self, String> {
+        // Only catalog files are downloaded and checked, a model given by
+        // path is the user's own
+        let model_file = precision.model_file();
+        if Path::new(model_path) == store.path_for(model_file) {
+            store.ensure(model_file, Path::new(model_path))?;
+        } else if !Path::new(model_path).is_file() {
+            return Err(format!("model file {} does not exist", model_path));
+        }
+        println!("load model from: {}", model_path);
+        let voices_path = match voices_path {
+            Some(path) => path.to_path_buf(),
+            None if has_voice_files(Path::new(SHIPPED_VOICES_DIR)) => PathBuf::from(SHIPPED_VOICES_DIR),
+            None => {
+                let path = store.path_for(&VOICES);
+                store.ensure(&VOICES, &path)?;
+                path
+            }
         };
 
+        if config.uses_accelerator() {
+            println!("Initializing with GPU configuration...");
+        } else {
+            println!("Initializing with CPU configuration...");
+        }
+
         let model = Arc::new(
             OrtKoko::with_config(model_path.to_string(), config)
-                .expect("Failed to create Kokoro TTS model"),
+                .map_err(|e| format!("Failed to create Kokoro TTS model: {}", e))?,
         );
 
         model.print_info();
+        // The file may hold another precision than the one asked for
+        if let Some(binding) = model.binding() {
+            println!("Model precision: {} (requested {})", binding.types(), precision);
+        }
 
         let mut instance = TTSKoko {
             model_path: model_path.to_string(),
             model,
             styles: HashMap::new(),
+            chunk_config: ChunkConfig::default(),
         };
-        instance.load_voices();
-        instance
+        instance.load_voices(&voices_path)?;
+        Ok(instance)
+    }
+
+    /// An instance with the synthetic voices "a" and "b" and no model, for
+    /// tests of code that never reaches inference.
+    #[cfg(test)]
+    pub(crate) fn synthetic() -> Self {
+        TTSKoko {
+            model_path: String::new(),
+            model: Arc::new(OrtKoko::unloaded()),
+            styles: tests::synthetic_styles(),
+            chunk_config: ChunkConfig::default(),
+        }
+    }
+
+    /// Sets how long inputs are split into chunks and joined back together.
+    pub fn with_chunk_config(mut self, chunk_config: ChunkConfig) -> Self {
+        self.chunk_config = chunk_config;
+        self
+    }
+
+    pub fn chunk_config(&self) -> &ChunkConfig {
+        &self.chunk_config
+    }
+
+    /// Execution provider the model runs on.
+    pub fn provider(&self) -> &Provider {
+        self.model.provider()
+    }
+
+    /// Number of chunks the model can infer at the same time, see
+    /// `OrtConfig::sessions`.
+    pub fn pool_size(&self) -> usize {
+        self.model.pool_size()
     }
 
+    /// Streams the audio for `txt` chunk by chunk as it is inferred on
+    /// Tokio's blocking pool. `TTSStream::new(&tts, ...)` gives the same
+    /// chunks from a blocking iterator.
+    pub fn tts_stream_async(
+        self: &Arc<Self>,
+        txt: &str,
+        lan: &str,
+        style_name: &str,
+        opts: TTSOpts,
+    ) -> Result<AudioStream, TtsError> {
+        let stream = TTSStream::new(Arc::clone(self), txt, lan, style_name, opts)?;
+        Ok(AudioStream::spawn(stream))
+    }
+
+    /// Synthesizes `txt` and returns the audio, leaving it to the caller to
+    /// decide whether and where to write it.
+    ///
+    /// The text is split into sentences, whose phonemes are split into
+    /// chunks that fit the model context. Each chunk is inferred separately
+    /// and the audio is joined with the silence and crossfade from the
+    /// `ChunkConfig`, recording where every sentence starts and ends.
     pub fn tts(
         &self,
         txt: &str,
         lan: &str,
         style_name: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        opts: &TTSOpts,
+    ) -> Result<AudioBuffer, Box<dyn std::error::Error>> {
         println!("hello, going to tts. text: {}", txt);
 
-        let txt = txt.trim();
-        if txt.is_empty() {
-            return Err("Empty text input".into());
+        if opts.timestamps {
+            let txt = txt.trim();
+            if txt.is_empty() {
+                return Err(TtsError::invalid("input", "Empty text input").into());
+            }
+            Self::validate_speed(opts.speed).map_err(|e| TtsError::invalid("speed", e))?;
+
+            // 先进行音素化处理
+            let phonemizer = Phonemizer::new(lan).map_err(|e| TtsError::invalid("language", e))?;
+            println!("Phonemizing with espeak-ng language: {}", phonemizer.language());
+            return self.tts_timed(txt, &phonemizer, style_name, opts);
         }
 
-        // 先进行音素化处理
-        let phonemes = text_to_phonemes(txt, lan, None, false, false)
-            .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?
-            .join("");  // 不添加空格，直接连接
-        
+        let start_t = Instant::now();
+        let mut collector = ChunkCollector::new();
+        for chunk in TTSStream::new(self, txt, lan, style_name, opts.clone())? {
+            collector.push(chunk?);
+        }
+
+        let audio = collector.finish(start_t.elapsed().as_secs_f32());
+        Self::report(&audio);
+        Ok(audio)
+    }
+
+    /// Synthesizes `txt` in the same chunks as `TTSStream`, mapping the
+    /// predicted durations back to each word to fill `AudioBuffer::words`.
+    fn tts_timed(
+        &self,
+        txt: &str,
+        phonemizer: &Phonemizer,
+        style_name: &str,
+        opts: &TTSOpts,
+    ) -> Result<AudioBuffer, Box<dyn std::error::Error>> {
+        if !self.model.has_durations() {
+            return Err(format!(
+                "{} has no duration output, word timestamps need a Kokoro export that outputs durations",
+                self.model_path
+            )
+            .into());
+        }
+        self.validate_style(style_name)
+            .map_err(|e| TtsError::invalid("voice", e))?;
+
+        let silence = ms_to_samples(self.chunk_config.sentence_silence_ms, TTSKoko::SAMPLE_RATE);
+        let crossfade = ms_to_samples(self.chunk_config.crossfade_ms, TTSKoko::SAMPLE_RATE);
+
+        let start_t = Instant::now();
+        let mut samples = Vec::new();
+        let mut unknown = Vec::new();
+        let mut timings = Vec::new();
+        let mut sentences = Vec::new();
+        let mut phonemes_len = 0;
+        let mut after_sentence = false;
+        for sentence in split_text_sentences(txt) {
+            let phonemes = phonemizer.phonemize(sentence, opts.normalize)?;
+            let tokenized = tokenize_with_policy(&phonemes, &opts.unknown_policy)
+                .map_err(|e| TtsError::invalid("unknown_chars", e))?;
+            unknown.extend(tokenized.unknown);
+            // Words are phonemized on their own only to tell where each is
+            let words = phonemizer.phonemize_words(sentence, opts.normalize)?;
+            let mut aligner = WordAligner::new(&words, &tokenized.phonemes);
+
+            for chunk in split_phonemes(&tokenized.phonemes, self.chunk_config.max_tokens) {
+                let (tokens, styles, tokens_len) = self.chunk_inputs(&chunk.phonemes, style_name)?;
+                let (audio, durations) = self.model.infer_with_durations(tokens, styles, opts.speed)?;
+                let chunk_samples: Vec<f32> = audio.iter().cloned().collect();
+
+                let gap = if after_sentence { silence } else { 0 };
+                join_samples(&mut samples, &chunk_samples, gap, crossfade);
+                let offset = samples.len() - chunk_samples.len();
+                aligner.push_chunk(
+                    &chunk.phonemes,
+                    &durations,
+                    chunk_samples.len(),
+                    offset,
+                    TTSKoko::SAMPLE_RATE,
+                );
+                phonemes_len += tokens_len;
+                after_sentence = chunk.ends_sentence;
+            }
+
+            let words = aligner.finish();
+            sentences.extend(sentence_timing(sentence, &words));
+            timings.extend(words);
+        }
+        if phonemes_len == 0 {
+            return Err(TtsError::invalid("input", "No phonemes to synthesize").into());
+        }
+
+        let mut audio = self.process_audio(start_t, samples, phonemes_len);
+        audio.unknown = unknown;
+        audio.words = timings;
+        audio.sentences = sentences;
+        Ok(audio)
+    }
+
+    /// Model inputs for one chunk of phonemes: the padded tokens, the style
+    /// row for its length and the number of phoneme tokens.
+    fn chunk_inputs(
+        &self,
+        phonemes: &str,
+        style_name: &str,
+    ) -> Result<ChunkInputs, Box<dyn std::error::Error>> {
         // 在音素序列前后添加标记，确保标记和音素之间没有空格
         let phonemes = format!("${}$", phonemes);
-        println!("Generated phonemes: {}", phonemes);
-        
         let tokens = vec![tokenize(&phonemes)];
         println!("Tokenized result: {:?}", tokens);
         println!("Tokenized result length: {}", tokens[0].len());
 
-        if let Ok(styles) = self.mix_styles(style_name) {
-            let start_t = Instant::now();
-            // println!("styles: {:?}", styles);
-            let result = self.model.infer(tokens, styles);
-            match result {
-                Ok(out) => {
-                    println!("output: {:?}", out);
-                    let phonemes_len = phonemes.len();
-                    self.process_and_save_audio(start_t, out, phonemes_len)?;
-                }
-                Err(e) => {
-                    eprintln!("An error occurred during inference: {:?}", e);
-                }
-            }
+        // The style row is picked by phoneme count, excluding the two `$` pads
+        let tokens_len = tokens[0].len().saturating_sub(2);
+        let styles = self
+            .mix_styles(style_name, tokens_len)
+            .map_err(|_| format!("{} failed to parse this style_name.", style_name))?;
+        Ok((tokens, styles, tokens_len))
+    }
+
+    /// Runs inference for one chunk of phonemes, returning its samples and
+    /// the number of phoneme tokens that were synthesized.
+    pub(crate) fn infer_chunk(
+        &self,
+        phonemes: &str,
+        style_name: &str,
+        speed: f32,
+    ) -> Result<(Vec<f32>, usize), Box<dyn std::error::Error>> {
+        let (tokens, styles, tokens_len) = self.chunk_inputs(phonemes, style_name)?;
+        let out = self.model.infer(tokens, styles, speed)?;
+        Ok((out.iter().cloned().collect(), tokens_len))
+    }
 
+    /// Checks that `speed` is a finite rate within `MIN_SPEED..=MAX_SPEED`.
+    pub fn validate_speed(speed: f32) -> Result<(), String> {
+        if speed.is_finite() && (Self::MIN_SPEED..=Self::MAX_SPEED).contains(&speed) {
             Ok(())
         } else {
-            Err(format!("{} failed to parse this style_name.", style_name).into())
+            Err(format!(
+                "speed must be between {} and {}, got {}",
+                Self::MIN_SPEED,
+                Self::MAX_SPEED,
+                speed
+            ))
         }
     }
 
+    /// Checks that every voice in `style_name`, single or mixed, is loaded.
+    pub fn validate_style(&self, style_name: &str) -> Result<(), String> {
+        validate_style(&self.styles, style_name)
+    }
+
     pub fn mix_styles(
         &self,
         style_name: &str,
+        tokens_len: usize,
     ) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
-        if !style_name.contains("+") {
-            if let Some(style) = self.styles.get(style_name) {
-                let styles = vec![style[0][0].to_vec()];
-                Ok(styles)
-            } else {
-                Err(format!("can not found from styles_map: {}", style_name).into())
-            }
-        } else {
-            println!("parsing style mix");
-            let styles: Vec<&str> = style_name.split('+').collect();
-
-            let mut style_names = Vec::new();
-            let mut style_portions = Vec::new();
-
-            for style in styles {
-                if let Some((name, portion)) = style.split_once('.') {
-                    if let Ok(portion) = portion.parse::<f32>() {
-                        style_names.push(name);
-                        style_portions.push(portion * 0.1);
-                    }
-                }
-            }
-            println!("styles: {:?}, portions: {:?}", style_names, style_portions);
-
-            let mut blended_style = vec![vec![0.0; 256]; 1];
-
-            for (name, portion) in style_names.iter().zip(style_portions.iter()) {
-                if let Some(style) = self.styles.get(*name) {
-                    let style_slice = &style[0][0]; // This is a [256] array
-                                                    // Blend into the blended_style
-                    for j in 0..256 {
-                        blended_style[0][j] += style_slice[j] * portion;
-                    }
-                }
-            }
-            Ok(blended_style)
-        }
+        mix_styles(&self.styles, style_name, tokens_len)
     }
 
-    fn process_and_save_audio(
+    fn process_audio(
         &self,
         start_t: Instant,
-        output: ArrayBase<OwnedRepr<f32>, IxDyn>,
+        samples: Vec<f32>,
         phonemes_len: usize,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Convert output to standard Vec
-        let audio: Vec<f32> = output.iter().cloned().collect();
-
-        let audio_duration = audio.len() as f32 / TTSKoko::SAMPLE_RATE as f32;
+    ) -> AudioBuffer {
         let create_duration = start_t.elapsed().as_secs_f32();
-        let speedup_factor = audio_duration / create_duration;
+        let audio = AudioBuffer::new(samples, TTSKoko::SAMPLE_RATE, phonemes_len, create_duration);
+        Self::report(&audio);
+        audio
+    }
 
+    fn report(audio: &AudioBuffer) {
         println!(
             "Created audio in length of {:.2}s for {} phonemes in {:.2}s ({:.2}x real-time)",
-            audio_duration, phonemes_len, create_duration, speedup_factor
+            audio.duration,
+            audio.phonemes_len,
+            audio.duration * audio.real_time_factor,
+            1.0 / audio.real_time_factor
         );
+    }
 
-        let spec = hound::WavSpec {
-            channels: 1,
-            sample_rate: TTSKoko::SAMPLE_RATE,
-            bits_per_sample: 32,
-            sample_format: hound::SampleFormat::Float,
-        };
+    /// Adds the voices of a voice file or directory, replacing voices of
+    /// the same name.
+    pub fn load_voices(&mut self, path: &Path) -> Result<(), String> {
+        println!("load voices from: {}", path.display());
+        self.styles.extend(load_voices(path)?);
 
-        let mut writer = hound::WavWriter::create("tmp/output.wav", spec)?;
+        let mut names: Vec<&String> = self.styles.keys().collect();
+        names.sort();
+        println!("voice styles loaded: {}", names.len());
+        println!("{:?}", names);
+        Ok(())
+    }
+}
 
-        for &sample in &audio {
-            writer.write_sample(sample)?;
+fn validate_style(styles: &HashMap<String, StyleTable>, style_name: &str) -> Result<(), String> {
+    if !style_name.contains('+') {
+        return if styles.contains_key(style_name) {
+            Ok(())
+        } else {
+            Err(format!("voice '{}' not found", style_name))
+        };
+    }
+
+    for part in style_name.split('+') {
+        let Some((name, portion)) = part.split_once('.') else {
+            return Err(format!("voice mix part '{}' must look like name.portion", part));
+        };
+        if portion.parse::<f32>().is_err() {
+            return Err(format!("invalid portion '{}' for voice '{}'", portion, name));
         }
+        if !styles.contains_key(name) {
+            return Err(format!("voice '{}' not found", name));
+        }
+    }
+    Ok(())
+}
 
-        writer.finalize()?;
+/// Picks the style row matching `tokens_len` for a single voice or a blended
+/// mix such as `af_sarah.4+af_nicole.6`.
+///
+/// Kokoro conditions on a different style vector per input length, so the
+/// row index is the token count, clamped to the last row of each table
+/// (v1.0 packs have one row less).
+fn mix_styles(
+    styles: &HashMap<String, StyleTable>,
+    style_name: &str,
+    tokens_len: usize,
+) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+    let row = |table: &StyleTable| tokens_len.min(table.len().saturating_sub(1));
+
+    if !style_name.contains("+") {
+        if let Some(style) = styles.get(style_name) {
+            let styles = vec![style[row(style)][0].to_vec()];
+            Ok(styles)
+        } else {
+            Err(format!("can not found from styles_map: {}", style_name).into())
+        }
+    } else {
+        println!("parsing style mix");
+        let style_parts: Vec<&str> = style_name.split('+').collect();
+
+        let mut style_names = Vec::new();
+        let mut style_portions = Vec::new();
+
+        for style in style_parts {
+            if let Some((name, portion)) = style.split_once('.') {
+                if let Ok(portion) = portion.parse::<f32>() {
+                    style_names.push(name);
+                    style_portions.push(portion * 0.1);
+                }
+            }
+        }
+        println!("styles: {:?}, portions: {:?}", style_names, style_portions);
 
-        println!("Audio saved to tmp/output.wav");
-        Ok(())
-    }
+        let mut blended_style = vec![vec![0.0; 256]; 1];
 
-    pub fn load_voices(&mut self) {
-        // load from json, get styles
-        let values = load_json_file(TTSKoko::JSON_DATA_F);
-        if let Ok(values) = values {
-            if let Some(obj) = values.as_object() {
-                for (key, value) in obj {
-                    // Check if value is an array
-                    if let Some(outer_array) = value.as_array() {
-                        // Define target multidimensional array
-                        let mut array_3d = [[[0.0; 256]; 1]; 511];
-
-                        // Iterate through outer array (511 elements)
-                        for (i, inner_value) in outer_array.iter().enumerate() {
-                            if let Some(middle_array) = inner_value.as_array() {
-                                // Iterate through middle array (1 element)
-                                for (j, inner_inner_value) in middle_array.iter().enumerate() {
-                                    if let Some(inner_array) = inner_inner_value.as_array() {
-                                        // Iterate through inner array (256 elements)
-                                        for (k, number) in inner_array.iter().enumerate() {
-                                            if let Some(num) = number.as_f64() {
-                                                array_3d[i][j][k] = num as f32;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
-                        // Insert multidimensional array into HashMap
-                        self.styles.insert(key.clone(), array_3d);
-                    }
+        for (name, portion) in style_names.iter().zip(style_portions.iter()) {
+            if let Some(style) = styles.get(*name) {
+                let style_slice = &style[row(style)][0]; // This is a [256] array
+                                                  // Blend into the blended_style
+                for j in 0..256 {
+                    blended_style[0][j] += style_slice[j] * portion;
                 }
             }
+        }
+        Ok(blended_style)
+    }
+}
 
-            println!("voice styles loaded: {}", self.styles.len());
-            println!("{:?}", self.styles.keys());
-            println!(
-                "{:?} {:?}",
-                self.styles.keys().next(),
-                self.styles.keys().nth(1)
-            );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tts::voices::{MIN_STYLE_ROWS, STYLE_ROWS};
+
+    /// Builds voices "a" and "b" whose row `i` is filled with `i` and `2 * i`.
+    pub(crate) fn synthetic_styles() -> HashMap<String, StyleTable> {
+        let mut styles = HashMap::new();
+        for (name, scale) in [("a", 1.0), ("b", 2.0)] {
+            let table: StyleTable = (0..STYLE_ROWS)
+                .map(|i| [[i as f32 * scale; 256]])
+                .collect();
+            styles.insert(name.to_string(), table);
         }
+        styles
+    }
+
+    #[test]
+    fn test_single_style_uses_token_length_row() {
+        let styles = synthetic_styles();
+
+        let style = mix_styles(&styles, "a", 0).unwrap();
+        assert_eq!(style.len(), 1);
+        assert_eq!(style[0].len(), 256);
+        assert!(style[0].iter().all(|&v| v == 0.0));
+
+        let style = mix_styles(&styles, "a", 42).unwrap();
+        assert!(style[0].iter().all(|&v| v == 42.0));
+
+        let style = mix_styles(&styles, "b", 100).unwrap();
+        assert!(style[0].iter().all(|&v| v == 200.0));
+    }
+
+    #[test]
+    fn test_token_length_is_clamped_to_table() {
+        let styles = synthetic_styles();
+
+        let style = mix_styles(&styles, "a", 510).unwrap();
+        assert!(style[0].iter().all(|&v| v == 510.0));
+
+        let style = mix_styles(&styles, "a", 10_000).unwrap();
+        assert!(style[0].iter().all(|&v| v == 510.0));
+    }
+
+    #[test]
+    fn test_token_length_is_clamped_to_v1_table() {
+        let mut styles = synthetic_styles();
+        styles.get_mut("b").unwrap().truncate(MIN_STYLE_ROWS);
+
+        let style = mix_styles(&styles, "b", 510).unwrap();
+        assert!(style[0].iter().all(|&v| v == 1018.0));
+
+        // 0.5 * 510 + 0.5 * 1018
+        let style = mix_styles(&styles, "a.5+b.5", 510).unwrap();
+        assert!(style[0].iter().all(|&v| (v - 764.0).abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_blended_style_uses_token_length_row() {
+        let styles = synthetic_styles();
+
+        // 0.4 * 10 + 0.6 * 20
+        let style = mix_styles(&styles, "a.4+b.6", 10).unwrap();
+        assert_eq!(style[0].len(), 256);
+        assert!(style[0].iter().all(|&v| (v - 16.0).abs() < 1e-4));
+
+        // 0.5 * 510 + 0.5 * 1020
+        let style = mix_styles(&styles, "a.5+b.5", 9_999).unwrap();
+        assert!(style[0].iter().all(|&v| (v - 765.0).abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_validate_speed() {
+        assert!(TTSKoko::validate_speed(1.0).is_ok());
+        assert!(TTSKoko::validate_speed(0.25).is_ok());
+        assert!(TTSKoko::validate_speed(4.0).is_ok());
+
+        assert!(TTSKoko::validate_speed(0.2).is_err());
+        assert!(TTSKoko::validate_speed(4.5).is_err());
+        assert!(TTSKoko::validate_speed(0.0).is_err());
+        assert!(TTSKoko::validate_speed(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn test_validate_style() {
+        let styles = synthetic_styles();
+        assert!(validate_style(&styles, "a").is_ok());
+        assert!(validate_style(&styles, "a.4+b.6").is_ok());
+
+        assert!(validate_style(&styles, "c").is_err());
+        assert!(validate_style(&styles, "a.4+c.6").is_err());
+        assert!(validate_style(&styles, "a+b").is_err());
+        assert!(validate_style(&styles, "a.x+b.6").is_err());
+    }
+
+    #[test]
+    fn test_unknown_style_is_an_error() {
+        let styles = synthetic_styles();
+        assert!(mix_styles(&styles, "missing", 10).is_err());
+    }
+
+    #[test]
+    fn test_missing_custom_model_is_not_downloaded() {
+        let store = ModelStore::new().with_offline(true);
+        let err = TTSKoko::load("missing/custom.onnx", None, Precision::Fp32, OrtConfig::default(), &store)
+            .err()
+            .unwrap();
+        assert_eq!(err, "model file missing/custom.onnx does not exist");
     }
 }
@@ -1,5 +1,13 @@
+pub mod audio;
+pub mod chunk;
+pub mod encode;
 pub mod koko;
 pub mod normalize;
 pub mod phonemizer;
+pub mod precision;
+pub mod stream;
+pub mod subtitles;
+pub mod timestamps;
 pub mod tokenize;
 pub mod vocab;
+pub mod voices;
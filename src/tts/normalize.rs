@@ -1,5 +1,8 @@
+use fancy_regex::{Captures, Regex};
 use lazy_static::lazy_static;
-use regex::Regex;
+
+// The patterns below rely on look-around, which the `regex` crate does not
+// support, hence `fancy_regex`.
 
 lazy_static! {
     static ref WHITESPACE_RE: Regex = Regex::new(r"[^\S \n]").unwrap();
@@ -27,13 +30,18 @@ lazy_static! {
     static ref ACRONYM_RE: Regex = Regex::new(r"(?i)(?<=[A-Z])\.(?=[A-Z])").unwrap();
 }
 
+/// Whether `normalize_text` applies to `lan`, its rules are English only.
+pub fn is_english(lan: &str) -> bool {
+    let lan = lan.to_ascii_lowercase();
+    lan == "en" || lan.starts_with("en-") || lan.starts_with("en_")
+}
+
 pub fn normalize_text(text: &str) -> String {
     let mut text = text.to_string();
 
     // Replace special quotes and brackets
-    text = text.replace('\u{2018}', "'").replace('\u{2019}', "'");
-    text = text.replace('«', "\u{201C}").replace('»', "\u{201D}");
-    text = text.replace('\u{201C}', "\"").replace('\u{201D}', "\"");
+    text = text.replace(['\u{2018}', '\u{2019}'], "'");
+    text = text.replace(['«', '»', '\u{201C}', '\u{201D}'], "\"");
     text = text.replace('(', "«").replace(')', "»");
 
     // Replace Chinese/Japanese punctuation
@@ -54,18 +62,354 @@ pub fn normalize_text(text: &str) -> String {
     text = MRS_RE.replace_all(&text, "Mrs").to_string();
     text = ETC_RE.replace_all(&text, "etc").to_string();
     text = YEAH_RE.replace_all(&text, "${1}e'a").to_string();
-    // Note: split_num, flip_money, and point_num functions need to be implemented
-    text = COMMA_NUM_RE.replace_all(&text, "").to_string();
+    // Ranges go first, once numbers are spelled out there are no digits left to match
     text = RANGE_RE.replace_all(&text, " to ").to_string();
+    text = NUMBERS_RE
+        .replace_all(&text, |caps: &Captures| {
+            let m = caps.get(0).unwrap();
+            // Leave amounts to flip_money, "$1984" is not a year
+            if text[..m.start()].ends_with(['$', '£']) {
+                m.as_str().to_string()
+            } else {
+                split_num(m.as_str())
+            }
+        })
+        .to_string();
+    text = COMMA_NUM_RE.replace_all(&text, "").to_string();
+    text = MONEY_RE
+        .replace_all(&text, |caps: &Captures| flip_money(&caps[0]))
+        .to_string();
+    text = POINT_NUM_RE
+        .replace_all(&text, |caps: &Captures| point_num(&caps[0]))
+        .to_string();
     text = S_AFTER_NUM_RE.replace_all(&text, " S").to_string();
     text = POSSESSIVE_RE.replace_all(&text, "'S").to_string();
     text = X_POSSESSIVE_RE.replace_all(&text, "s").to_string();
 
     // Handle initials and acronyms
     text = INITIALS_RE
-        .replace_all(&text, |caps: &regex::Captures| caps[0].replace('.', "-"))
+        .replace_all(&text, |caps: &Captures| caps[0].replace('.', "-"))
         .to_string();
     text = ACRONYM_RE.replace_all(&text, "-").to_string();
 
     text.trim().to_string()
 }
+
+const ONES: [&str; 20] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen",
+    "nineteen",
+];
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+const SCALES: [&str; 7] = [
+    "", "thousand", "million", "billion", "trillion", "quadrillion", "quintillion",
+];
+
+/// Spells out a cardinal number, e.g. 1984 -> "one thousand nine hundred eighty-four".
+pub fn number_to_words(n: u64) -> String {
+    if n == 0 {
+        return ONES[0].to_string();
+    }
+
+    let mut groups = Vec::new();
+    let mut rest = n;
+    let mut scale = 0;
+    while rest > 0 {
+        let group = rest % 1000;
+        if group > 0 {
+            let words = below_thousand(group);
+            groups.push(match SCALES[scale] {
+                "" => words,
+                scale_word => format!("{} {}", words, scale_word),
+            });
+        }
+        rest /= 1000;
+        scale += 1;
+    }
+    groups.reverse();
+    groups.join(" ")
+}
+
+/// Spells out 1..=999.
+fn below_thousand(n: u64) -> String {
+    let (hundreds, rest) = (n / 100, n % 100);
+    match (hundreds, rest) {
+        (0, _) => below_hundred(rest),
+        (_, 0) => format!("{} hundred", ONES[hundreds as usize]),
+        _ => format!("{} hundred {}", ONES[hundreds as usize], below_hundred(rest)),
+    }
+}
+
+/// Spells out 0..=99.
+fn below_hundred(n: u64) -> String {
+    let n = n as usize;
+    match (n / 10, n % 10) {
+        (0..=1, _) => ONES[n].to_string(),
+        (tens, 0) => TENS[tens].to_string(),
+        (tens, ones) => format!("{}-{}", TENS[tens], ONES[ones]),
+    }
+}
+
+/// Spells out a run of digits, as a number when it fits in a u64 and digit by
+/// digit otherwise.
+fn digits_to_words(digits: &str) -> String {
+    match digits.parse::<u64>() {
+        Ok(n) => number_to_words(n),
+        Err(_) => spell_digits(digits),
+    }
+}
+
+/// Reads digits one at a time, e.g. "14" -> "one four".
+fn spell_digits(digits: &str) -> String {
+    digits
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|d| ONES[d as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Plural of a spelled-out number, e.g. "ninety" -> "nineties", "six" -> "sixes".
+fn pluralize(words: &str) -> String {
+    if let Some(stem) = words.strip_suffix('y') {
+        format!("{}ies", stem)
+    } else if words.ends_with('x') {
+        format!("{}es", words)
+    } else {
+        format!("{}s", words)
+    }
+}
+
+/// Reads years, decades and clock times matched by `NUMBERS_RE`.
+///
+/// Decimals are left alone for `point_num`.
+fn split_num(num: &str) -> String {
+    if num.contains('.') {
+        return num.to_string();
+    }
+
+    if let Some((h, m)) = num.split_once(':') {
+        let (h, m) = match (h.parse::<u64>(), m.parse::<u64>()) {
+            (Ok(h), Ok(m)) => (h, m),
+            _ => return num.to_string(),
+        };
+        return match m {
+            0 => format!("{} o'clock", number_to_words(h)),
+            1..=9 => format!("{} oh {}", number_to_words(h), number_to_words(m)),
+            _ => format!("{} {}", number_to_words(h), number_to_words(m)),
+        };
+    }
+
+    let plural = num.ends_with('s');
+    let year: u64 = match num[..4].parse() {
+        Ok(year) => year,
+        Err(_) => return num.to_string(),
+    };
+    let plural_if = |words: String| if plural { pluralize(&words) } else { words };
+
+    // Read as a plain number, e.g. "1066", "2005"
+    if year < 1100 || year % 1000 < 10 {
+        return plural_if(number_to_words(year));
+    }
+
+    let (left, right) = (below_hundred(year / 100), year % 100);
+    if (100..=999).contains(&(year % 1000)) {
+        if right == 0 {
+            return format!("{} {}", left, plural_if("hundred".to_string()));
+        } else if right < 10 {
+            return format!("{} oh {}", left, plural_if(number_to_words(right)));
+        }
+    }
+    format!("{} {}", left, plural_if(below_hundred(right)))
+}
+
+/// Reads currency amounts matched by `MONEY_RE`, e.g. "$5.50" -> "five
+/// dollars and fifty cents", "£3 million" -> "three million pounds".
+fn flip_money(m: &str) -> String {
+    let is_dollar = m.starts_with('$');
+    let bill = if is_dollar { "dollar" } else { "pound" };
+    let amount = &m[m.chars().next().map_or(0, char::len_utf8)..];
+
+    // Amounts with a magnitude word, e.g. "$1.5 billion"
+    if amount.ends_with(|c: char| c.is_alphabetic()) {
+        let (number, magnitude) = amount.split_once(' ').unwrap_or((amount, ""));
+        return format!("{} {} {}s", decimal_to_words(number), magnitude.to_lowercase(), bill);
+    }
+
+    let Some((whole, fraction)) = amount.split_once('.') else {
+        let s = if amount == "1" { "" } else { "s" };
+        return format!("{} {}{}", digits_to_words(amount), bill, s);
+    };
+
+    // More than two decimals are not cents, read them as a decimal amount
+    if fraction.len() > 2 {
+        return format!("{} {}s", decimal_to_words(amount), bill);
+    }
+
+    let s = if whole == "1" { "" } else { "s" };
+    let bills = format!("{} {}{}", digits_to_words(whole), bill, s);
+    let cents: u64 = format!("{:0<2}", fraction).parse().unwrap_or(0);
+    if cents == 0 {
+        return bills;
+    }
+    let coins = match (is_dollar, cents) {
+        (true, 1) => "cent",
+        (true, _) => "cents",
+        (false, 1) => "penny",
+        (false, _) => "pence",
+    };
+    format!("{} and {} {}", bills, number_to_words(cents), coins)
+}
+
+/// Reads decimals matched by `POINT_NUM_RE`, e.g. "3.14" -> "three point one four".
+fn point_num(num: &str) -> String {
+    decimal_to_words(num)
+}
+
+fn decimal_to_words(num: &str) -> String {
+    match num.split_once('.') {
+        Some(("", fraction)) => format!("point {}", spell_digits(fraction)),
+        Some((whole, fraction)) => {
+            format!("{} point {}", digits_to_words(whole), spell_digits(fraction))
+        }
+        None => digits_to_words(num),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_english() {
+        assert!(is_english("en-us"));
+        assert!(is_english("en-GB"));
+        assert!(is_english("en"));
+        assert!(!is_english("de-de"));
+        assert!(!is_english("zh-cn"));
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(normalize_text("Dr. Smith met Mr. Jones"), "Doctor Smith met Mister Jones");
+        assert_eq!(normalize_text("pages 10-20"), "pages 10 to 20");
+        assert_eq!(normalize_text("the 1,000 BBQs"), "the 1000 BBQ'S");
+        assert_eq!(normalize_text("U.S.A. is big"), "U-S-A- is big");
+        assert_eq!(normalize_text("“quoted”  text\t"), "\"quoted\" text");
+    }
+
+    fn check(cases: &[(&str, &str)]) {
+        for (input, expected) in cases {
+            assert_eq!(normalize_text(input), *expected, "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_number_to_words() {
+        let cases: &[(u64, &str)] = &[
+            (0, "zero"),
+            (7, "seven"),
+            (13, "thirteen"),
+            (40, "forty"),
+            (84, "eighty-four"),
+            (100, "one hundred"),
+            (101, "one hundred one"),
+            (1984, "one thousand nine hundred eighty-four"),
+            (20_000, "twenty thousand"),
+            (1_000_001, "one million one"),
+            (u64::MAX, "eighteen quintillion four hundred forty-six quadrillion seven hundred forty-four trillion seventy-three billion seven hundred nine million five hundred fifty-one thousand six hundred fifteen"),
+        ];
+        for (n, expected) in cases {
+            assert_eq!(number_to_words(*n), *expected, "n: {}", n);
+        }
+    }
+
+    /// `NUMBERS_RE`: years and decades.
+    #[test]
+    fn test_years_and_decades() {
+        check(&[
+            ("1984", "nineteen eighty-four"),
+            ("in 1900", "in nineteen hundred"),
+            ("1905", "nineteen oh five"),
+            ("2010", "twenty ten"),
+            ("2005", "two thousand five"),
+            ("2000", "two thousand"),
+            ("1066", "one thousand sixty-six"),
+            ("the 1990s", "the nineteen nineties"),
+            ("the 1900s", "the nineteen hundreds"),
+            ("the 2000s", "the two thousands"),
+            ("the 1960s and 1970s", "the nineteen sixties and nineteen seventies"),
+            ("12345", "12345"),
+        ]);
+    }
+
+    /// `NUMBERS_RE`: clock times.
+    #[test]
+    fn test_clock_times() {
+        check(&[
+            ("at 3:00", "at three o'clock"),
+            ("at 3:05", "at three oh five"),
+            ("at 10:30", "at ten thirty"),
+            ("at 12:45 pm", "at twelve forty-five pm"),
+            ("13:30", "13:30"),
+            ("10:30:15", "10:30:15"),
+        ]);
+    }
+
+    /// `COMMA_NUM_RE`: thousands separators, read after years so "1,984" is not a year.
+    #[test]
+    fn test_comma_numbers() {
+        check(&[
+            ("1,000,000 people", "1000000 people"),
+            ("1,984", "1984"),
+            ("a, b", "a, b"),
+        ]);
+    }
+
+    /// `MONEY_RE`: dollars and pounds.
+    #[test]
+    fn test_money() {
+        check(&[
+            ("$1", "one dollar"),
+            ("$5", "five dollars"),
+            ("£20", "twenty pounds"),
+            ("$1984", "one thousand nine hundred eighty-four dollars"),
+            ("$1,250", "one thousand two hundred fifty dollars"),
+            ("$5.50", "five dollars and fifty cents"),
+            ("$5.5", "five dollars and fifty cents"),
+            ("$1.01", "one dollar and one cent"),
+            ("$5.00", "five dollars"),
+            ("£1.01", "one pound and one penny"),
+            ("£2.99", "two pounds and ninety-nine pence"),
+            ("$3 million", "three million dollars"),
+            ("$1.5 billion", "one point five billion dollars"),
+            ("£2 Trillion", "two trillion pounds"),
+            ("$2.125", "two point one two five dollars"),
+        ]);
+    }
+
+    /// `POINT_NUM_RE`: decimals.
+    #[test]
+    fn test_decimals() {
+        check(&[
+            ("3.14", "three point one four"),
+            ("pi is 3.14159", "pi is three point one four one five nine"),
+            (".5 liters", "point five liters"),
+            ("0.05", "zero point zero five"),
+            ("10.0", "ten point zero"),
+        ]);
+    }
+
+    /// `RANGE_RE`: numeric ranges, including ranges of years and times.
+    #[test]
+    fn test_ranges() {
+        check(&[
+            ("10-20", "10 to 20"),
+            ("1939-1945", "nineteen thirty-nine to nineteen forty-five"),
+            ("9:00-5:30", "nine o'clock to five thirty"),
+            ("well-known", "well-known"),
+        ]);
+    }
+}
@@ -1,7 +1,9 @@
 use crate::tts::normalize;
-use crate::tts::vocab::VOCAB;
+use crate::tts::vocab::PUNCTUATION;
+use fancy_regex::Regex;
 use lazy_static::lazy_static;
-use regex::Regex;
+
+use espeak_rs::text_to_phonemes;
 
 lazy_static! {
     static ref PHONEME_PATTERNS: Regex = Regex::new(r"(?<=[a-zɹː])(?=hˈʌndɹɪd)").unwrap();
@@ -9,7 +11,20 @@ lazy_static! {
     static ref NINETY_PATTERN: Regex = Regex::new(r"(?<=nˈaɪn)ti(?!ː)").unwrap();
 }
 
-// Placeholder for the EspeakBackend struct
+/// Maps the single-letter Kokoro language codes to espeak-ng voices, any
+/// other code is passed to espeak-ng as is.
+const KOKORO_LANGUAGES: [(&str, &str); 9] = [
+    ("a", "en-us"),
+    ("b", "en-gb"),
+    ("e", "es"),
+    ("f", "fr-fr"),
+    ("h", "hi"),
+    ("i", "it"),
+    ("j", "ja"),
+    ("p", "pt-br"),
+    ("z", "cmn"),
+];
+
 struct EspeakBackend {
     language: String,
     preserve_punctuation: bool,
@@ -25,10 +40,20 @@ impl EspeakBackend {
         }
     }
 
-    fn phonemize(&self, text: &[String]) -> Option<Vec<String>> {
-        // Implementation would go here
-        // This is where you'd integrate with actual espeak bindings
-        todo!("Implement actual phonemization")
+    /// Phonemizes each text, joining the clauses espeak-ng returns for it.
+    fn phonemize(&self, text: &[String]) -> Result<Vec<String>, String> {
+        text.iter()
+            .map(|t| {
+                let ps = text_to_phonemes(t, &self.language, None, true, !self.with_stress)
+                    .map_err(|e| e.to_string())?
+                    .join("");
+                if self.preserve_punctuation {
+                    Ok(ps)
+                } else {
+                    Ok(ps.chars().filter(|c| !PUNCTUATION.contains(*c) || *c == ' ').collect())
+                }
+            })
+            .collect()
     }
 }
 
@@ -38,55 +63,140 @@ pub struct Phonemizer {
 }
 
 impl Phonemizer {
-    pub fn new(lang: &str) -> Self {
-        let backend = match lang {
-            "a" => EspeakBackend::new("en-us", true, true),
-            "b" => EspeakBackend::new("en-gb", true, true),
-            _ => panic!("Unsupported language"),
-        };
+    /// Creates a phonemizer for a Kokoro language code ("a", "b", ...) or a
+    /// full espeak-ng language code ("en-us", "de", ...).
+    pub fn new(lang: &str) -> Result<Self, String> {
+        let lang = resolve_language(lang)?;
+        Ok(Phonemizer {
+            backend: EspeakBackend::new(&lang, true, true),
+            lang,
+        })
+    }
 
-        Phonemizer {
-            lang: lang.to_string(),
-            backend,
-        }
+    /// The espeak-ng language this phonemizer runs with.
+    pub fn language(&self) -> &str {
+        &self.lang
     }
 
-    pub fn phonemize(&self, text: &str, normalize: bool) -> String {
-        let text = if normalize {
+    /// Phonemizes `text`, normalizing it first if asked to and the language
+    /// is English, then applies the Kokoro-specific fixes.
+    pub fn phonemize(&self, text: &str, normalize: bool) -> Result<String, String> {
+        let text = if normalize && normalize::is_english(&self.lang) {
             normalize::normalize_text(text)
         } else {
             text.to_string()
         };
 
-        // Assume phonemize returns Option<String>
-        let mut ps = match self.backend.phonemize(&[text]) {
-            Some(phonemes) => phonemes[0].clone(),
-            None => String::new(),
-        };
+        let ps = self.backend.phonemize(&[text])?.concat();
+        Ok(post_process(&ps, self.lang == "en-us"))
+    }
 
-        // Apply kokoro-specific replacements
-        ps = ps
-            .replace("kəkˈoːɹoʊ", "kˈoʊkəɹoʊ")
-            .replace("kəkˈɔːɹəʊ", "kˈəʊkəɹəʊ");
+    /// Phonemizes `text` one whitespace-separated word at a time, to tell
+    /// how many phonemes each word takes. The audio is synthesized from the
+    /// whole sentence, see `WordAligner`.
+    ///
+    /// Words are normalized on their own, rules spanning several words such
+    /// as `$5 million` are not applied.
+    pub fn phonemize_words(&self, text: &str, normalize: bool) -> Result<Vec<(String, String)>, String> {
+        text.split_whitespace()
+            .map(|word| {
+                let phonemes = self.phonemize(word, normalize)?;
+                Ok((word.to_string(), strip_clause_ending(word, &phonemes)))
+            })
+            .collect()
+    }
+}
 
-        // Apply character replacements
-        ps = ps
-            .replace("ʲ", "j")
-            .replace("r", "ɹ")
-            .replace("x", "k")
-            .replace("ɬ", "l");
+/// espeak-ng ends every clause it phonemizes with a `.`, even a lone word
+/// the text does not end there. Drops that `.` when `word` has no ending
+/// punctuation of its own.
+fn strip_clause_ending(word: &str, phonemes: &str) -> String {
+    let ends_word = word.ends_with(|c: char| c.is_ascii_punctuation() || PUNCTUATION.contains(c));
+    match phonemes.strip_suffix('.') {
+        Some(stripped) if !ends_word => stripped.to_string(),
+        _ => phonemes.to_string(),
+    }
+}
 
-        // Apply regex patterns
-        ps = PHONEME_PATTERNS.replace_all(&ps, " ").to_string();
-        ps = Z_PATTERN.replace_all(&ps, "z").to_string();
+/// Turns a Kokoro or espeak-ng language code into an espeak-ng one.
+fn resolve_language(lang: &str) -> Result<String, String> {
+    let lang = lang.trim();
+    if let Some((_, espeak)) = KOKORO_LANGUAGES.iter().find(|(code, _)| *code == lang) {
+        return Ok(espeak.to_string());
+    }
 
-        if self.lang == "a" {
-            ps = NINETY_PATTERN.replace_all(&ps, "di").to_string();
-        }
+    let valid = lang.len() > 1
+        && lang
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(lang.to_ascii_lowercase())
+    } else {
+        Err(format!("Unsupported language: {:?}", lang))
+    }
+}
+
+/// Kokoro-specific fixes on top of the raw espeak-ng output.
+fn post_process(ps: &str, american: bool) -> String {
+    // Apply kokoro-specific replacements
+    let mut ps = ps
+        .replace("kəkˈoːɹoʊ", "kˈoʊkəɹoʊ")
+        .replace("kəkˈɔːɹəʊ", "kˈəʊkəɹəʊ");
+
+    // Apply character replacements
+    ps = ps
+        .replace("ʲ", "j")
+        .replace("r", "ɹ")
+        .replace("x", "k")
+        .replace("ɬ", "l");
+
+    // Apply regex patterns
+    ps = PHONEME_PATTERNS.replace_all(&ps, " ").to_string();
+    ps = Z_PATTERN.replace_all(&ps, "z").to_string();
 
-        // Filter characters present in vocabulary
-        ps = ps.chars().filter(|&c| VOCAB.contains_key(&c)).collect();
+    if american {
+        ps = NINETY_PATTERN.replace_all(&ps, "di").to_string();
+    }
+
+    // Characters missing from the vocab are left in, `tokenize_with_policy`
+    // reports and handles them.
+    ps.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_language() {
+        assert_eq!(resolve_language("a").unwrap(), "en-us");
+        assert_eq!(resolve_language("b").unwrap(), "en-gb");
+        assert_eq!(resolve_language("z").unwrap(), "cmn");
+        assert_eq!(resolve_language("en-US").unwrap(), "en-us");
+        assert_eq!(resolve_language("de").unwrap(), "de");
+        assert_eq!(resolve_language("cmn-latn-pinyin").unwrap(), "cmn-latn-pinyin");
+
+        assert!(resolve_language("").is_err());
+        assert!(resolve_language("q").is_err());
+        assert!(resolve_language("en us").is_err());
+    }
+
+    #[test]
+    fn test_post_process() {
+        assert_eq!(post_process("kəkˈoːɹoʊ", true), "kˈoʊkəɹoʊ");
+        assert_eq!(post_process("rˈʌʲ", true), "ɹˈʌj");
+        assert_eq!(post_process("wʌnhˈʌndɹɪd", true), "wʌn hˈʌndɹɪd");
+        assert_eq!(post_process("ɪts z.", true), "ɪtsz.");
+        assert_eq!(post_process("nˈaɪnti", true), "nˈaɪndi");
+        assert_eq!(post_process("nˈaɪnti", false), "nˈaɪnti");
+    }
 
-        ps.trim().to_string()
+    #[test]
+    fn test_strip_clause_ending() {
+        // What espeak-rs gives for single words
+        assert_eq!(strip_clause_ending("test", "tˈɛst."), "tˈɛst");
+        assert_eq!(strip_clause_ending("end.", "ˈɛnd."), "ˈɛnd.");
+        assert_eq!(strip_clause_ending("Hello,", "həlˈoʊ."), "həlˈoʊ.");
+        assert_eq!(strip_clause_ending("ok", "ˌoʊkˈeɪ"), "ˌoʊkˈeɪ");
     }
 }
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::utils::models::{ModelFile, KOKORO_FP16, KOKORO_FP32, KOKORO_INT8, KOKORO_Q4};
+
+/// Numeric precision of the model weights. Smaller ones load faster and
+/// use less memory, int8 and q4 also run faster on CPUs, at some cost in
+/// audio quality. All hold the Kokoro v0.19 weights, so the same voices
+/// work with them, but fp16, int8 and q4 are the onnx-community exports,
+/// which take `input_ids` where fp32 takes `tokens`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+    #[default]
+    Fp32,
+    /// Half precision floats, mostly useful on GPUs.
+    Fp16,
+    /// 8-bit quantized weights and activations.
+    Int8,
+    /// 4-bit quantized weights of the matrix multiplications.
+    Q4,
+}
+
+impl Precision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Precision::Fp32 => "fp32",
+            Precision::Fp16 => "fp16",
+            Precision::Int8 => "int8",
+            Precision::Q4 => "q4",
+        }
+    }
+
+    /// The model file of this precision.
+    pub fn model_file(&self) -> &'static ModelFile {
+        match self {
+            Precision::Fp32 => &KOKORO_FP32,
+            Precision::Fp16 => &KOKORO_FP16,
+            Precision::Int8 => &KOKORO_INT8,
+            Precision::Q4 => &KOKORO_Q4,
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fp32" | "f32" => Ok(Precision::Fp32),
+            "fp16" | "f16" => Ok(Precision::Fp16),
+            "int8" | "q8" => Ok(Precision::Int8),
+            "q4" | "int4" => Ok(Precision::Q4),
+            other => Err(format!(
+                "unknown precision '{}', expected fp32, fp16, int8 or q4",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_precision() {
+        for precision in [Precision::Fp32, Precision::Fp16, Precision::Int8, Precision::Q4] {
+            assert_eq!(precision.as_str().parse::<Precision>(), Ok(precision));
+        }
+        assert_eq!("INT4".parse::<Precision>(), Ok(Precision::Q4));
+        assert!("bf16".parse::<Precision>().is_err());
+    }
+
+    #[test]
+    fn test_model_files_match_names() {
+        for precision in [Precision::Fp32, Precision::Fp16, Precision::Int8, Precision::Q4] {
+            assert_eq!(precision.model_file().name, precision.as_str());
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::Stream;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::tts::audio::{ms_to_samples, AudioBuffer, StreamJoiner};
+use crate::tts::chunk::{split_phonemes, split_text_sentences, PhonemeChunk};
+use crate::tts::koko::{TTSKoko, TTSOpts, TtsError};
+use crate::tts::phonemizer::Phonemizer;
+use crate::tts::timestamps::SentenceTiming;
+use crate::tts::tokenize::{tokenize_with_policy, UnknownChar};
+
+/// Chunks the async stream may synthesize ahead of its consumer.
+const STREAM_BUFFER: usize = 4;
+
+/// How often a full stream channel is checked for room, see `send_within`.
+const SEND_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A piece of audio yielded by a synthesis stream.
+#[derive(Debug, Clone)]
+pub struct AudioChunk {
+    /// Position of the chunk in the stream, starting at 0.
+    pub index: usize,
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    /// Phonemes synthesized for this chunk, empty for the final tail.
+    pub phonemes: String,
+    /// Number of phoneme tokens synthesized for this chunk.
+    pub phonemes_len: usize,
+    /// Phoneme characters the vocab could not tokenize, with positions
+    /// relative to the sentence they were found in.
+    pub unknown: Vec<UnknownChar>,
+    /// Time from the start of the stream until the chunk was ready.
+    pub latency: Duration,
+    /// Index of the input sentence the chunk was synthesized from.
+    pub sentence: usize,
+    /// Text of that sentence, empty for the final tail.
+    pub text: String,
+    /// Start and end of the speech synthesized for this chunk, in seconds
+    /// from the start of the stream. Because of the held back crossfade,
+    /// `samples` itself can start slightly earlier.
+    pub start: f32,
+    pub end: f32,
+}
+
+/// Synthesizes text sentence by sentence, yielding audio as soon as each
+/// chunk is inferred.
+///
+/// Concatenating the samples of every chunk gives the same joins as
+/// `TTSKoko::tts`, see `StreamJoiner`.
+pub struct TTSStream<T: Deref<Target = TTSKoko>> {
+    tts: T,
+    phonemizer: Phonemizer,
+    style_name: String,
+    opts: TTSOpts,
+    sentences: VecDeque<String>,
+    chunks: VecDeque<PhonemeChunk>,
+    /// Number of sentences queued so far and the text of the last one, its
+    /// chunks are all consumed before the next sentence is queued.
+    sentence: usize,
+    sentence_text: String,
+    unknown: Vec<UnknownChar>,
+    joiner: StreamJoiner,
+    after_sentence: bool,
+    start: Instant,
+    index: usize,
+    done: bool,
+}
+
+impl<T: Deref<Target = TTSKoko>> TTSStream<T> {
+    /// Validates the request and splits `txt` into sentences, nothing is
+    /// phonemized or inferred until the stream is polled.
+    pub fn new(tts: T, txt: &str, lan: &str, style_name: &str, opts: TTSOpts) -> Result<Self, TtsError> {
+        let txt = txt.trim();
+        if txt.is_empty() {
+            return Err(TtsError::invalid("input", "Empty text input"));
+        }
+        TTSKoko::validate_speed(opts.speed).map_err(|e| TtsError::invalid("speed", e))?;
+        tts.validate_style(style_name)
+            .map_err(|e| TtsError::invalid("voice", e))?;
+        let phonemizer = Phonemizer::new(lan).map_err(|e| TtsError::invalid("language", e))?;
+
+        let sentences = split_text_sentences(txt).into_iter().map(str::to_string).collect();
+        let config = tts.chunk_config();
+        let joiner = StreamJoiner::new(
+            ms_to_samples(config.sentence_silence_ms, TTSKoko::SAMPLE_RATE),
+            ms_to_samples(config.crossfade_ms, TTSKoko::SAMPLE_RATE),
+        );
+        Ok(TTSStream {
+            tts,
+            phonemizer,
+            style_name: style_name.to_string(),
+            opts,
+            sentences,
+            chunks: VecDeque::new(),
+            sentence: 0,
+            sentence_text: String::new(),
+            unknown: Vec::new(),
+            joiner,
+            after_sentence: false,
+            start: Instant::now(),
+            index: 0,
+            done: false,
+        })
+    }
+
+    /// Phonemizes the next sentence and queues its chunks.
+    fn queue_sentence(&mut self, sentence: &str) -> Result<(), TtsError> {
+        let phonemes = self.phonemizer.phonemize(sentence, self.opts.normalize)?;
+        println!("Streaming sentence: {} -> {}", sentence, phonemes);
+
+        let tokenized = tokenize_with_policy(&phonemes, &self.opts.unknown_policy)
+            .map_err(|e| TtsError::invalid("unknown_chars", e))?;
+        println!(
+            "Tokenized {} phonemes, {} unsupported",
+            tokenized.tokens.len(),
+            tokenized.unknown.len()
+        );
+        self.unknown.extend(tokenized.unknown);
+        let chunks = split_phonemes(&tokenized.phonemes, self.tts.chunk_config().max_tokens);
+        self.chunks.extend(chunks);
+        self.sentence += 1;
+        self.sentence_text = sentence.to_string();
+        Ok(())
+    }
+
+    fn next_chunk(&mut self, chunk: PhonemeChunk) -> Result<AudioChunk, TtsError> {
+        let (samples, phonemes_len) = self
+            .tts
+            .infer_chunk(&chunk.phonemes, &self.style_name, self.opts.speed)
+            .map_err(|e| TtsError::Failed(e.to_string()))?;
+
+        let speech_len = samples.len();
+        let samples = self.joiner.push(&samples, self.after_sentence);
+        self.after_sentence = chunk.ends_sentence;
+        let text = self.sentence_text.clone();
+        Ok(self.emit(samples, chunk.phonemes, phonemes_len, text, speech_len))
+    }
+
+    /// Wraps `samples` into the next chunk, whose own speech is the last
+    /// `speech_len` samples joined so far.
+    fn emit(
+        &mut self,
+        samples: Vec<f32>,
+        phonemes: String,
+        phonemes_len: usize,
+        text: String,
+        speech_len: usize,
+    ) -> AudioChunk {
+        let latency = self.start.elapsed();
+        if self.index == 0 {
+            println!("First audio chunk ready in {:.2?}", latency);
+        }
+
+        let seconds = |samples: usize| samples as f32 / TTSKoko::SAMPLE_RATE as f32;
+        let end = self.joiner.joined_len();
+        let chunk = AudioChunk {
+            index: self.index,
+            samples,
+            sample_rate: TTSKoko::SAMPLE_RATE,
+            phonemes,
+            phonemes_len,
+            unknown: std::mem::take(&mut self.unknown),
+            latency,
+            sentence: self.sentence.saturating_sub(1),
+            text,
+            start: seconds(end - speech_len),
+            end: seconds(end),
+        };
+        self.index += 1;
+        chunk
+    }
+}
+
+impl<T: Deref<Target = TTSKoko>> Iterator for TTSStream<T> {
+    type Item = Result<AudioChunk, TtsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if let Some(chunk) = self.chunks.pop_front() {
+                let result = self.next_chunk(chunk);
+                self.done = result.is_err();
+                return Some(result);
+            }
+
+            if let Some(sentence) = self.sentences.pop_front() {
+                if let Err(e) = self.queue_sentence(&sentence) {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                continue;
+            }
+
+            self.done = true;
+            if self.index == 0 {
+                return Some(Err(TtsError::invalid("input", "No phonemes to synthesize")));
+            }
+            let tail = self.joiner.finish();
+            if !tail.is_empty() {
+                return Some(Ok(self.emit(tail, String::new(), 0, String::new(), 0)));
+            }
+        }
+        None
+    }
+}
+
+/// Gathers the chunks of a stream back into one `AudioBuffer`, with the
+/// timing of every sentence.
+#[derive(Debug, Default)]
+pub struct ChunkCollector {
+    samples: Vec<f32>,
+    phonemes_len: usize,
+    unknown: Vec<UnknownChar>,
+    sentences: Vec<SentenceTiming>,
+    last_sentence: Option<usize>,
+}
+
+impl ChunkCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, chunk: AudioChunk) {
+        if !chunk.text.is_empty() {
+            match self.sentences.last_mut() {
+                Some(last) if self.last_sentence == Some(chunk.sentence) => last.end = chunk.end,
+                _ => {
+                    self.sentences.push(SentenceTiming {
+                        text: chunk.text,
+                        start: chunk.start,
+                        end: chunk.end,
+                    });
+                    self.last_sentence = Some(chunk.sentence);
+                }
+            }
+        }
+        self.samples.extend(chunk.samples);
+        self.phonemes_len += chunk.phonemes_len;
+        self.unknown.extend(chunk.unknown);
+    }
+
+    /// Builds the audio, `elapsed` being the synthesis time in seconds.
+    pub fn finish(self, elapsed: f32) -> AudioBuffer {
+        let mut audio = AudioBuffer::new(self.samples, TTSKoko::SAMPLE_RATE, self.phonemes_len, elapsed);
+        audio.unknown = self.unknown;
+        audio.sentences = self.sentences;
+        audio
+    }
+}
+
+/// Async counterpart of `TTSStream`, inference runs on Tokio's blocking pool
+/// and stops once the stream is dropped.
+pub struct AudioStream {
+    rx: mpsc::Receiver<Result<AudioChunk, TtsError>>,
+}
+
+impl AudioStream {
+    /// Starts synthesizing `stream` in the background, must be called from
+    /// within a Tokio runtime.
+    pub fn spawn<T>(stream: TTSStream<T>) -> Self
+    where
+        T: Deref<Target = TTSKoko> + Send + 'static,
+    {
+        let (audio, producer) = Self::pipe(stream, None);
+        tokio::task::spawn_blocking(producer);
+        audio
+    }
+
+    /// Returns the async stream along with the blocking loop that feeds it,
+    /// for callers running inference on threads of their own.
+    ///
+    /// With a `send_timeout`, the loop gives up on a consumer that has not
+    /// made room for the next chunk within that time, ending the stream
+    /// early, so that a client that stopped reading cannot hold the thread.
+    pub fn pipe<T>(mut stream: TTSStream<T>, send_timeout: Option<Duration>) -> (Self, impl FnOnce() + Send + 'static)
+    where
+        T: Deref<Target = TTSKoko> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let producer = move || {
+            // Stop synthesizing once the receiver is dropped
+            while !tx.is_closed() {
+                let Some(chunk) = stream.next() else { break };
+                let sent = match send_timeout {
+                    Some(timeout) => send_within(&tx, chunk, timeout),
+                    None => tx.blocking_send(chunk).map_err(|_| SendError::Closed),
+                };
+                match sent {
+                    Ok(()) => {}
+                    Err(SendError::Closed) => break,
+                    Err(SendError::TimedOut) => {
+                        eprintln!(
+                            "Stream consumer did not read for {:.0?}, stopping synthesis",
+                            send_timeout.unwrap_or_default()
+                        );
+                        break;
+                    }
+                }
+            }
+        };
+        (AudioStream { rx }, producer)
+    }
+}
+
+/// Why a chunk could not be handed to the consumer.
+#[derive(Debug, PartialEq, Eq)]
+enum SendError {
+    Closed,
+    TimedOut,
+}
+
+/// Blocks until `item` is sent, the receiver is dropped or `timeout` has
+/// passed. Tokio's timed send needs a runtime, which worker threads lack, so
+/// a full channel is polled.
+fn send_within<T>(tx: &mpsc::Sender<T>, mut item: T, timeout: Duration) -> Result<(), SendError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match tx.try_send(item) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Closed(_)) => return Err(SendError::Closed),
+            Err(TrySendError::Full(back)) if Instant::now() < deadline => {
+                item = back;
+                std::thread::sleep(SEND_POLL_INTERVAL.min(timeout));
+            }
+            Err(TrySendError::Full(_)) => return Err(SendError::TimedOut),
+        }
+    }
+}
+
+impl Stream for AudioStream {
+    type Item = Result<AudioChunk, TtsError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(sentence: usize, text: &str, start: f32, end: f32) -> AudioChunk {
+        AudioChunk {
+            index: 0,
+            samples: vec![0.0; 10],
+            sample_rate: TTSKoko::SAMPLE_RATE,
+            phonemes: String::new(),
+            phonemes_len: 2,
+            unknown: Vec::new(),
+            latency: Duration::ZERO,
+            sentence,
+            text: text.to_string(),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn test_collector_merges_sentence_chunks() {
+        let mut collector = ChunkCollector::new();
+        collector.push(chunk(0, "A long one.", 0.0, 1.0));
+        collector.push(chunk(0, "A long one.", 1.0, 2.5));
+        collector.push(chunk(1, "Short.", 2.6, 3.0));
+        collector.push(chunk(1, "", 3.0, 3.0));
+        let audio = collector.finish(1.0);
+
+        assert_eq!(audio.samples.len(), 40);
+        assert_eq!(audio.phonemes_len, 8);
+        assert_eq!(
+            audio.sentences,
+            vec![
+                SentenceTiming {
+                    text: "A long one.".to_string(),
+                    start: 0.0,
+                    end: 2.5
+                },
+                SentenceTiming {
+                    text: "Short.".to_string(),
+                    start: 2.6,
+                    end: 3.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_send_within_gives_up_on_stalled_consumer() {
+        let (tx, mut rx) = mpsc::channel(1);
+        assert_eq!(send_within(&tx, 1, Duration::from_millis(20)), Ok(()));
+        let start = Instant::now();
+        assert_eq!(send_within(&tx, 2, Duration::from_millis(30)), Err(SendError::TimedOut));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(send_within(&tx, 3, Duration::from_millis(20)), Ok(()));
+        drop(rx);
+        assert_eq!(send_within(&tx, 4, Duration::from_millis(20)), Err(SendError::Closed));
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::tts::audio::AudioBuffer;
+use crate::tts::chunk::sentence_ends;
+use crate::tts::timestamps::{SentenceTiming, WordTiming};
+
+/// Longest cue, two lines of `LINE_CHARS`.
+const MAX_CUE_CHARS: usize = 84;
+/// Line length cues are wrapped at, the usual broadcast limit.
+const LINE_CHARS: usize = 42;
+
+/// Caption file formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt,
+}
+
+impl SubtitleFormat {
+    /// Guesses the format from the extension of `path`.
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Self> {
+        path.as_ref().extension()?.to_str()?.parse().ok()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Vtt => "vtt",
+        }
+    }
+
+    /// Writes `cues` as a caption file.
+    pub fn render(&self, cues: &[Cue]) -> String {
+        let mut out = String::new();
+        if *self == SubtitleFormat::Vtt {
+            out.push_str("WEBVTT\n\n");
+        }
+
+        let separator = match self {
+            SubtitleFormat::Srt => ',',
+            SubtitleFormat::Vtt => '.',
+        };
+        for (i, cue) in cues.iter().enumerate() {
+            if *self == SubtitleFormat::Srt {
+                let _ = writeln!(out, "{}", i + 1);
+            }
+            let _ = writeln!(
+                out,
+                "{} --> {}",
+                timestamp(cue.start, separator),
+                timestamp(cue.end, separator)
+            );
+            let _ = writeln!(out, "{}\n", wrap_lines(&cue.text, LINE_CHARS));
+        }
+        out
+    }
+}
+
+impl FromStr for SubtitleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "srt" => Ok(SubtitleFormat::Srt),
+            "vtt" | "webvtt" => Ok(SubtitleFormat::Vtt),
+            other => Err(format!("unknown subtitle format '{}', expected srt or vtt", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for SubtitleFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One caption, in seconds from the start of the audio.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Cue {
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+}
+
+/// Captions for `audio`, cut at sentence boundaries and kept under two
+/// lines.
+///
+/// With word timings (`TTSOpts::with_timestamps`) long sentences are cut
+/// between words at their exact times. Otherwise a long sentence's time is
+/// shared between its pieces by their length.
+pub fn cues(audio: &AudioBuffer) -> Vec<Cue> {
+    if audio.words.is_empty() {
+        audio.sentences.iter().flat_map(sentence_cues).collect()
+    } else {
+        word_cues(&audio.words)
+    }
+}
+
+fn word_cues(words: &[WordTiming]) -> Vec<Cue> {
+    // Words are joined back to find the sentence ends, as in the input text
+    let mut text = String::new();
+    let mut word_ends = Vec::with_capacity(words.len());
+    for word in words {
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(&word.word);
+        word_ends.push(text.len());
+    }
+    let ends = sentence_ends(&text);
+
+    let mut cues = Vec::new();
+    let mut current: Vec<&WordTiming> = Vec::new();
+    for (word, end) in words.iter().zip(word_ends) {
+        if !current.is_empty() && cue_len(&current) + 1 + word.word.chars().count() > MAX_CUE_CHARS {
+            cues.push(words_cue(&current));
+            current.clear();
+        }
+        current.push(word);
+        if ends.contains(&end) {
+            cues.push(words_cue(&current));
+            current.clear();
+        }
+    }
+    if !current.is_empty() {
+        cues.push(words_cue(&current));
+    }
+    cues
+}
+
+fn cue_len(words: &[&WordTiming]) -> usize {
+    words.iter().map(|w| w.word.chars().count()).sum::<usize>() + words.len().saturating_sub(1)
+}
+
+fn words_cue(words: &[&WordTiming]) -> Cue {
+    Cue {
+        start: words[0].start,
+        end: words[words.len() - 1].end,
+        text: words.iter().map(|w| w.word.as_str()).collect::<Vec<_>>().join(" "),
+    }
+}
+
+fn sentence_cues(sentence: &SentenceTiming) -> Vec<Cue> {
+    let pieces = split_words(&sentence.text, MAX_CUE_CHARS);
+    let total: usize = pieces.iter().map(|p| p.chars().count()).sum();
+    let span = sentence.end - sentence.start;
+
+    let mut cues = Vec::with_capacity(pieces.len());
+    let mut start = sentence.start;
+    let mut chars = 0;
+    for piece in pieces {
+        chars += piece.chars().count();
+        let end = if chars == total {
+            sentence.end
+        } else {
+            sentence.start + span * chars as f32 / total as f32
+        };
+        cues.push(Cue { start, end, text: piece });
+        start = end;
+    }
+    cues
+}
+
+/// Splits `text` between words into pieces of at most `max_chars`, a
+/// longer word getting a piece of its own.
+fn split_words(text: &str, max_chars: usize) -> Vec<String> {
+    let mut pieces: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > max_chars {
+            pieces.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Breaks a cue into lines of at most `line_chars`.
+fn wrap_lines(text: &str, line_chars: usize) -> String {
+    split_words(text, line_chars).join("\n")
+}
+
+/// Formats `seconds` as `HH:MM:SS,mmm`, with `separator` before the
+/// milliseconds.
+fn timestamp(seconds: f32, separator: char) -> String {
+    let ms = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(word: &str, start: f32, end: f32) -> WordTiming {
+        WordTiming {
+            word: word.to_string(),
+            start,
+            end,
+            phonemes: Vec::new(),
+        }
+    }
+
+    fn sentence(text: &str, start: f32, end: f32) -> SentenceTiming {
+        SentenceTiming {
+            text: text.to_string(),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0.0, ','), "00:00:00,000");
+        assert_eq!(timestamp(3661.2345, '.'), "01:01:01.235");
+    }
+
+    #[test]
+    fn test_render_srt_and_vtt() {
+        let cues = vec![
+            Cue { start: 0.0, end: 1.5, text: "Hello there.".to_string() },
+            Cue { start: 1.6, end: 2.0, text: "Bye.".to_string() },
+        ];
+        assert_eq!(
+            SubtitleFormat::Srt.render(&cues),
+            "1\n00:00:00,000 --> 00:00:01,500\nHello there.\n\n2\n00:00:01,600 --> 00:00:02,000\nBye.\n\n"
+        );
+        assert_eq!(
+            SubtitleFormat::Vtt.render(&cues),
+            "WEBVTT\n\n00:00:00.000 --> 00:00:01.500\nHello there.\n\n00:00:01.600 --> 00:00:02.000\nBye.\n\n"
+        );
+    }
+
+    #[test]
+    fn test_sentence_cues() {
+        let mut audio = AudioBuffer::new(vec![0.0; 10], 10, 0, 0.0);
+        audio.sentences = vec![sentence("Hello there.", 0.1, 1.0), sentence("Bye.", 1.2, 1.5)];
+        let cues = cues(&audio);
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0], Cue { start: 0.1, end: 1.0, text: "Hello there.".to_string() });
+        assert_eq!(cues[1].start, 1.2);
+    }
+
+    #[test]
+    fn test_long_sentence_is_split() {
+        let text = "word ".repeat(40);
+        let cues = sentence_cues(&sentence(text.trim(), 0.0, 10.0));
+        assert!(cues.len() > 1);
+        assert!(cues.iter().all(|c| c.text.chars().count() <= MAX_CUE_CHARS));
+        assert_eq!(cues[0].start, 0.0);
+        assert_eq!(cues.last().unwrap().end, 10.0);
+        for pair in cues.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+    }
+
+    #[test]
+    fn test_word_cues_follow_sentences() {
+        let words = vec![
+            word("Dr.", 0.0, 0.3),
+            word("Smith", 0.3, 0.6),
+            word("left.", 0.6, 1.0),
+            word("Then", 1.2, 1.4),
+            word("rain.", 1.4, 1.8),
+        ];
+        let cues = word_cues(&words);
+        assert_eq!(
+            cues,
+            vec![
+                Cue { start: 0.0, end: 1.0, text: "Dr. Smith left.".to_string() },
+                Cue { start: 1.2, end: 1.8, text: "Then rain.".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wrap_lines() {
+        let text = "The quick brown fox jumps over the lazy dog and keeps running";
+        let wrapped = wrap_lines(text, LINE_CHARS);
+        assert_eq!(wrapped.lines().count(), 2);
+        assert!(wrapped.lines().all(|l| l.chars().count() <= LINE_CHARS));
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(SubtitleFormat::from_extension("out.SRT"), Some(SubtitleFormat::Srt));
+        assert_eq!(SubtitleFormat::from_extension("out.vtt"), Some(SubtitleFormat::Vtt));
+        assert_eq!(SubtitleFormat::from_extension("out.txt"), None);
+    }
+}
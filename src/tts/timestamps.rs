@@ -0,0 +1,248 @@
+use serde::Serialize;
+
+use crate::tts::vocab::{PUNCTUATION, VOCAB};
+
+/// Start and end of one phoneme, in seconds from the start of the audio.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhonemeTiming {
+    pub phoneme: char,
+    pub start: f32,
+    pub end: f32,
+}
+
+/// Start and end of one source word, in seconds from the start of the
+/// audio, with the phonemes it was read as.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WordTiming {
+    pub word: String,
+    pub start: f32,
+    pub end: f32,
+    pub phonemes: Vec<PhonemeTiming>,
+}
+
+/// Start and end of one sentence of the input text, in seconds from the
+/// start of the audio. Silence between sentences is left out.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SentenceTiming {
+    pub text: String,
+    pub start: f32,
+    pub end: f32,
+}
+
+/// Whether a phoneme is spoken, as opposed to punctuation, a space or a
+/// character the vocab cannot tokenize.
+fn is_spoken(phoneme: char) -> bool {
+    !PUNCTUATION.contains(phoneme) && VOCAB.contains_key(&phoneme)
+}
+
+/// Number of spoken phonemes in `phonemes`.
+pub fn spoken_len(phonemes: &str) -> usize {
+    phonemes.chars().filter(|&c| is_spoken(c)).count()
+}
+
+/// Times the words of one sentence from the predicted durations of the
+/// chunks it was synthesized in.
+///
+/// The sentence is phonemized as a whole, as without timestamps, so its
+/// phonemes are shared between its words in order: each word gets a share
+/// of the spoken phonemes in proportion to its length when phonemized on
+/// its own. When the words read the same alone as in the sentence, the
+/// usual case, every word gets exactly its own phonemes. Normalization or
+/// espeak-ng reading words together only shifts the boundaries around them.
+pub struct WordAligner {
+    words: Vec<String>,
+    /// Spoken phonemes of the sentence up to the end of each word.
+    ends: Vec<usize>,
+    /// Spoken phonemes timed so far.
+    spoken: usize,
+    timings: Vec<(usize, WordTiming)>,
+}
+
+impl WordAligner {
+    /// `words` are the words of the sentence with their phonemes on their
+    /// own, `sentence_phonemes` the phonemes the sentence is synthesized from.
+    pub fn new(words: &[(String, String)], sentence_phonemes: &str) -> Self {
+        let total = spoken_len(sentence_phonemes);
+        let lens: Vec<usize> = words.iter().map(|(_, phonemes)| spoken_len(phonemes)).collect();
+        let sum: usize = lens.iter().sum();
+        let mut len_so_far = 0;
+        let ends = lens
+            .iter()
+            .map(|len| {
+                len_so_far += len;
+                (len_so_far * total + sum / 2).checked_div(sum).unwrap_or(0)
+            })
+            .collect();
+
+        WordAligner {
+            words: words.iter().map(|(word, _)| word.clone()).collect(),
+            ends,
+            spoken: 0,
+            timings: Vec::new(),
+        }
+    }
+
+    /// Times the next chunk of the sentence.
+    ///
+    /// The chunk was synthesized from `phonemes`, padded with `$` on both
+    /// sides, and `durations` has one entry per token of that input.
+    /// Durations are in model frames, which are scaled to the
+    /// `chunk_samples` the chunk produced, and the chunk starts
+    /// `offset_samples` into the audio. Punctuation is left out of the
+    /// phonemes, so a comma's pause does not stretch the word before it.
+    pub fn push_chunk(
+        &mut self,
+        phonemes: &str,
+        durations: &[f32],
+        chunk_samples: usize,
+        offset_samples: usize,
+        sample_rate: u32,
+    ) {
+        let total: f32 = durations.iter().sum();
+        let samples_per_frame = if total > 0.0 { chunk_samples as f32 / total } else { 0.0 };
+        let seconds = |frames: f32| (offset_samples as f32 + frames * samples_per_frame) / sample_rate as f32;
+
+        // Frames elapsed before each token, skipping the leading pad
+        let mut frame = durations.first().copied().unwrap_or(0.0);
+        for (i, phoneme) in phonemes.chars().enumerate() {
+            let duration = durations.get(i + 1).copied().unwrap_or(0.0);
+            if is_spoken(phoneme) {
+                let word = self.ends.iter().position(|&end| end > self.spoken);
+                if let Some(word) = word {
+                    let timing = PhonemeTiming {
+                        phoneme,
+                        start: seconds(frame),
+                        end: seconds(frame + duration),
+                    };
+                    match self.timings.last_mut() {
+                        Some((last, current)) if *last == word => {
+                            current.end = timing.end;
+                            current.phonemes.push(timing);
+                        }
+                        _ => self.timings.push((
+                            word,
+                            WordTiming {
+                                word: self.words[word].clone(),
+                                start: timing.start,
+                                end: timing.end,
+                                phonemes: vec![timing],
+                            },
+                        )),
+                    }
+                }
+                self.spoken += 1;
+            }
+            frame += duration;
+        }
+    }
+
+    /// The timed words, leaving out those without spoken phonemes.
+    pub fn finish(self) -> Vec<WordTiming> {
+        self.timings.into_iter().map(|(_, timing)| timing).collect()
+    }
+}
+
+/// Spans `words`, the timed words of the sentence `text`, or `None` if
+/// there are none.
+pub fn sentence_timing(text: &str, words: &[WordTiming]) -> Option<SentenceTiming> {
+    let (first, last) = (words.first()?, words.last()?);
+    Some(SentenceTiming {
+        text: text.to_string(),
+        start: first.start,
+        end: last.end,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[(&str, &str)]) -> Vec<(String, String)> {
+        words.iter().map(|(w, p)| (w.to_string(), p.to_string())).collect()
+    }
+
+    #[test]
+    fn test_word_timings() {
+        let words = words(&[("Hi,", "hi,"), ("you", "ju")]);
+        let mut aligner = WordAligner::new(&words, "hi, ju");
+        // $ h i , ␠ j u $
+        let durations = [2.0, 1.0, 1.0, 2.0, 1.0, 1.0, 1.0, 1.0];
+        // 10 frames over 1000 samples at 1 kHz, 0.1s per frame
+        aligner.push_chunk("hi, ju", &durations, 1000, 0, 1000);
+        let timings = aligner.finish();
+
+        assert_eq!(timings.len(), 2);
+        assert_eq!(timings[0].word, "Hi,");
+        assert!((timings[0].start - 0.2).abs() < 1e-6);
+        // The comma's pause is not part of the word
+        assert!((timings[0].end - 0.4).abs() < 1e-6);
+        let phonemes: Vec<char> = timings[0].phonemes.iter().map(|p| p.phoneme).collect();
+        assert_eq!(phonemes, vec!['h', 'i']);
+
+        assert_eq!(timings[1].word, "you");
+        assert!((timings[1].start - 0.7).abs() < 1e-6);
+        assert!((timings[1].end - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_word_timings_offset() {
+        let mut aligner = WordAligner::new(&words(&[("a", "a")]), "a");
+        aligner.push_chunk("a", &[1.0, 1.0, 1.0], 300, 600, 100);
+        let timings = aligner.finish();
+        assert!((timings[0].start - 7.0).abs() < 1e-6);
+        assert!((timings[0].end - 8.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_words_of_espeak_clauses() {
+        // espeak-rs output: each word alone, and the sentence, whose clauses
+        // all end with `.`
+        let words = words(&[("Hello,", "həlˈoʊ."), ("big", "bˈɪɡ"), ("world.", "wˈɜːld.")]);
+        let sentence = "həlˈoʊ.bˈɪɡ wˈɜːld.";
+        let mut aligner = WordAligner::new(&words, sentence);
+        aligner.push_chunk("həlˈoʊ.", &[1.0; 9], 900, 0, 1000);
+        aligner.push_chunk("bˈɪɡ wˈɜːld.", &[1.0; 14], 1400, 900, 1000);
+        let timings = aligner.finish();
+
+        let read: Vec<(&str, String)> = timings
+            .iter()
+            .map(|t| (t.word.as_str(), t.phonemes.iter().map(|p| p.phoneme).collect()))
+            .collect();
+        assert_eq!(
+            read,
+            vec![
+                ("Hello,", "həlˈoʊ".to_string()),
+                ("big", "bˈɪɡ".to_string()),
+                ("world.", "wˈɜːld".to_string()),
+            ]
+        );
+        assert!((timings[1].start - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_words_read_differently_share_the_sentence() {
+        // `$5` alone and within the sentence read differently
+        let words = words(&[("$5", "fˈaɪv dˈɑːlɚz"), ("million", "mˈɪljən")]);
+        let sentence = "fˈaɪv mˈɪljən dˈɑːlɚz";
+        let mut aligner = WordAligner::new(&words, sentence);
+        aligner.push_chunk(sentence, &[1.0; 23], 2300, 0, 1000);
+        let timings = aligner.finish();
+
+        assert_eq!(timings.len(), 2);
+        let spoken: usize = timings.iter().map(|t| t.phonemes.len()).sum();
+        assert_eq!(spoken, spoken_len(sentence));
+        assert!(timings[0].end <= timings[1].start);
+    }
+
+    #[test]
+    fn test_sentence_timing() {
+        let mut aligner = WordAligner::new(&words(&[("Hi,", "hi,"), ("you.", "ju.")]), "hi, ju.");
+        aligner.push_chunk("hi, ju.", &[2.0, 1.0, 1.0, 2.0, 1.0, 1.0, 1.0, 1.0, 1.0], 1100, 0, 1000);
+        let timings = aligner.finish();
+        let sentence = sentence_timing("Hi, you.", &timings).unwrap();
+        assert_eq!(sentence.text, "Hi, you.");
+        assert_eq!(sentence.start, timings[0].start);
+        assert_eq!(sentence.end, timings[1].end);
+        assert!(sentence_timing("", &[]).is_none());
+    }
+}
@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::fmt;
+
 use crate::tts::vocab::VOCAB;
 
 /// Tokenizes the given phonemes string into a vector of token indices.
@@ -19,6 +22,132 @@ pub fn tokenize(phonemes: &str) -> Vec<i64> {
         .collect()
 }
 
+/// Replacements for characters espeak-ng may emit that are not in the vocab.
+pub fn default_substitutions() -> HashMap<char, String> {
+    [
+        ('ʦ', "ts"),
+        ('ʣ', "dz"),
+        ('ʨ', "tɕ"),
+        ('ʥ', "dʑ"),
+        ('-', " "),
+        ('\u{0303}', ""),
+    ]
+    .into_iter()
+    .filter(|(c, _)| !VOCAB.contains_key(c))
+    .map(|(c, s)| (c, s.to_string()))
+    .collect()
+}
+
+/// What to do with phoneme characters that are not in the vocab.
+#[derive(Debug, Clone, Default)]
+pub enum UnknownPolicy {
+    /// Drop them, the historical behaviour.
+    #[default]
+    Drop,
+    /// Replace them using the table, dropping those without an entry.
+    Substitute(HashMap<char, String>),
+    /// Fail tokenization.
+    Error,
+}
+
+impl UnknownPolicy {
+    /// Substitution with `default_substitutions`.
+    pub fn substitute() -> Self {
+        UnknownPolicy::Substitute(default_substitutions())
+    }
+}
+
+impl std::str::FromStr for UnknownPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(UnknownPolicy::Drop),
+            "substitute" => Ok(UnknownPolicy::substitute()),
+            "error" => Ok(UnknownPolicy::Error),
+            _ => Err(format!(
+                "unknown policy {:?}, expected one of: drop, substitute, error",
+                s
+            )),
+        }
+    }
+}
+
+/// A phoneme character that is not in the vocab.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownChar {
+    pub ch: char,
+    /// Position in the phoneme string, in characters.
+    pub position: usize,
+    /// What it was replaced with, `None` if it was dropped.
+    pub replacement: Option<String>,
+}
+
+impl fmt::Display for UnknownChar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} (U+{:04X}) at {}", self.ch, self.ch as u32, self.position)?;
+        match &self.replacement {
+            Some(r) => write!(f, " replaced with {:?}", r),
+            None => write!(f, " dropped"),
+        }
+    }
+}
+
+/// Tokenization result along with what happened to unknown characters.
+#[derive(Debug, Clone)]
+pub struct Tokenized {
+    /// The phonemes after the policy was applied, every character is in the vocab.
+    pub phonemes: String,
+    pub tokens: Vec<i64>,
+    pub unknown: Vec<UnknownChar>,
+}
+
+/// Tokenizes `phonemes`, handling characters missing from the vocab
+/// according to `policy` and listing each of them.
+pub fn tokenize_with_policy(phonemes: &str, policy: &UnknownPolicy) -> Result<Tokenized, String> {
+    let mut clean = String::with_capacity(phonemes.len());
+    let mut unknown = Vec::new();
+
+    for (position, ch) in phonemes.chars().enumerate() {
+        if VOCAB.contains_key(&ch) {
+            clean.push(ch);
+            continue;
+        }
+
+        let replacement = match policy {
+            UnknownPolicy::Substitute(table) => table
+                .get(&ch)
+                .map(|r| r.chars().filter(|c| VOCAB.contains_key(c)).collect::<String>()),
+            _ => None,
+        };
+        if let Some(r) = &replacement {
+            clean.push_str(r);
+        }
+        unknown.push(UnknownChar {
+            ch,
+            position,
+            replacement,
+        });
+    }
+
+    if matches!(policy, UnknownPolicy::Error) && !unknown.is_empty() {
+        let chars: Vec<String> = unknown
+            .iter()
+            .map(|u| format!("{:?} (U+{:04X}) at {}", u.ch, u.ch as u32, u.position))
+            .collect();
+        return Err(format!(
+            "phonemes contain characters the vocab cannot tokenize: {}",
+            chars.join(", ")
+        ));
+    }
+
+    Ok(Tokenized {
+        tokens: tokenize(&clean),
+        phonemes: clean,
+        unknown,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -29,7 +158,9 @@ mod tests {
         let tokens = tokenize(text);
 
         // Expected tokens based on the vocabulary mapping defined in get_vocab()
-        let expected = vec![24, 47, 54, 54, 57, 5];
+        let expected = vec![
+            50, 47, 102, 16, 81, 102, 61, 16, 102, 68, 16, 156, 54, 138, 64, 54, 51, 158, 5,
+        ];
 
         assert_eq!(tokens, expected);
 
@@ -43,6 +174,50 @@ mod tests {
         let punct_tokens = tokenize(punct);
         assert_eq!(punct_tokens.len(), 3);
     }
+
+    #[test]
+    fn test_policy_drop_reports_positions() {
+        let result = tokenize_with_policy("aʦb(c", &UnknownPolicy::Drop).unwrap();
+        assert_eq!(result.phonemes, "abc");
+        assert_eq!(result.tokens, tokenize("abc"));
+        assert_eq!(
+            result.unknown,
+            vec![
+                UnknownChar { ch: 'ʦ', position: 1, replacement: None },
+                UnknownChar { ch: '(', position: 3, replacement: None },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_policy_substitute() {
+        let result = tokenize_with_policy("aʦb(c", &UnknownPolicy::substitute()).unwrap();
+        assert_eq!(result.phonemes, "atsbc");
+        assert_eq!(result.unknown[0].replacement.as_deref(), Some("ts"));
+        assert_eq!(result.unknown[1].replacement, None);
+
+        let table = HashMap::from([('(', "«".to_string())]);
+        let result = tokenize_with_policy("a(b", &UnknownPolicy::Substitute(table)).unwrap();
+        assert_eq!(result.phonemes, "a«b");
+    }
+
+    #[test]
+    fn test_policy_error() {
+        let err = tokenize_with_policy("abʦ", &UnknownPolicy::Error).unwrap_err();
+        assert!(err.contains("'ʦ'"), "{}", err);
+        assert!(err.contains("at 2"), "{}", err);
+
+        let ok = tokenize_with_policy("ab", &UnknownPolicy::Error).unwrap();
+        assert!(ok.unknown.is_empty());
+    }
+
+    #[test]
+    fn test_default_substitutions_are_tokenizable() {
+        for (ch, replacement) in default_substitutions() {
+            assert!(!VOCAB.contains_key(&ch));
+            assert!(replacement.chars().all(|c| VOCAB.contains_key(&c)), "{:?}", replacement);
+        }
+    }
 }
 
 use crate::tts::vocab::REVERSE_VOCAB;
@@ -1,13 +1,16 @@
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 
+/// Padding symbol placed around every token sequence.
+pub const PAD: &str = "$";
+/// Punctuation symbols understood by the model.
+pub const PUNCTUATION: &str = ";:,.!?¡¿—…\"«»“” ";
+
 pub fn get_vocab() -> std::collections::HashMap<char, usize> {
-    let pad = "$";
-    let punctuation = ";:,.!?¡¿—…\"«»“” ";
     let letters = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
     let letters_ipa = "ɑɐɒæɓʙβɔɕçɗɖðʤəɘɚɛɜɝɞɟʄɡɠɢʛɦɧħɥʜɨɪʝɭɬɫɮʟɱɯɰŋɳɲɴøɵɸθœɶʘɹɺɾɻʀʁɽʂʃʈʧʉʊʋⱱʌɣɤʍχʎʏʑʐʒʔʡʕʢǀǁǂǃˈˌːˑʼʴʰʱʲʷˠˤ˞↓↑→↗↘'̩'ᵻ";
 
-    let symbols: String = [pad, punctuation, letters, letters_ipa].concat();
+    let symbols: String = [PAD, PUNCTUATION, letters, letters_ipa].concat();
 
     symbols
         .chars()
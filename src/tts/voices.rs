@@ -0,0 +1,768 @@
+//! Voice style packs, read from the files Kokoro voices are published as:
+//! PyTorch `.pt` tensors, NumPy `.npy` arrays, `.npz` archives of them
+//! (which is what `voices.bin` is) and the older `voices.json`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+
+use half::{bf16, f16};
+use serde_json::Value;
+use zip::ZipArchive;
+
+/// Number of rows in a voice style table, one per possible input token length.
+pub const STYLE_ROWS: usize = 511;
+
+/// Number of rows in the Kokoro v1.0 voice packs, which stop one row short.
+pub const MIN_STYLE_ROWS: usize = 510;
+
+/// Length of one style vector.
+pub const STYLE_DIM: usize = 256;
+
+/// Per-voice style table of `STYLE_ROWS` (or `MIN_STYLE_ROWS`) rows, one
+/// 256-dim style vector per input token length. Kept on the heap, a full
+/// table is ~0.5MB.
+pub type StyleTable = Vec<[[f32; STYLE_DIM]; 1]>;
+
+/// Extensions of the files voices are loaded from.
+pub const VOICE_EXTENSIONS: [&str; 5] = ["pt", "npy", "npz", "bin", "json"];
+
+/// Directory of the voices shipped with the repository, used ahead of the
+/// downloaded voices when run from a checkout.
+pub const SHIPPED_VOICES_DIR: &str = "data";
+
+/// Whether `dir` is a directory with a voice file in it.
+pub fn has_voice_files(dir: &Path) -> bool {
+    fs::read_dir(dir).is_ok_and(|entries| {
+        entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .any(|file| file.is_file() && has_voice_extension(&file))
+    })
+}
+
+/// Loads the voices of a file, or of every voice file in a directory. A
+/// `.pt` or `.npy` file holds one voice named after the file, the others
+/// hold several. Every file that fails is listed in the error.
+pub fn load_voices(path: &Path) -> Result<HashMap<String, StyleTable>, String> {
+    if !path.is_dir() {
+        return load_voice_file(path)
+            .map(|voices| voices.into_iter().collect())
+            .map_err(|e| format!("failed to load voices from {}: {}", path.display(), e));
+    }
+
+    let entries = fs::read_dir(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.is_file() && has_voice_extension(file))
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        return Err(format!(
+            "no voice files in {}, expected .{} files",
+            path.display(),
+            VOICE_EXTENSIONS.join(", .")
+        ));
+    }
+
+    let mut styles = HashMap::new();
+    let mut errors = Vec::new();
+    for file in &files {
+        match load_voice_file(file) {
+            Ok(voices) => styles.extend(voices),
+            Err(e) => errors.push(format!("  {}: {}", file.display(), e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(format!(
+            "failed to load {} of {} voice files:\n{}",
+            errors.len(),
+            files.len(),
+            errors.join("\n")
+        ));
+    }
+    Ok(styles)
+}
+
+fn has_voice_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| VOICE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Loads the voices of one file, chosen by its extension.
+pub fn load_voice_file(path: &Path) -> Result<Vec<(String, StyleTable)>, String> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    if !VOICE_EXTENSIONS.contains(&ext.as_str()) {
+        return Err(format!(
+            "unknown voice file type '.{}', expected .{}",
+            ext,
+            VOICE_EXTENSIONS.join(", .")
+        ));
+    }
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+
+    match ext.as_str() {
+        "pt" => Ok(vec![(name, read_pt(&bytes)?.into_style_table()?)]),
+        "npy" => Ok(vec![(name, read_npy(&bytes)?.into_style_table()?)]),
+        "json" => read_json(&bytes),
+        _ => read_npz(&bytes),
+    }
+}
+
+/// Element types voice tensors are stored as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dtype {
+    F16,
+    Bf16,
+    F32,
+    F64,
+}
+
+impl Dtype {
+    fn size(self) -> usize {
+        match self {
+            Dtype::F16 | Dtype::Bf16 => 2,
+            Dtype::F32 => 4,
+            Dtype::F64 => 8,
+        }
+    }
+
+    /// Reads `count` values from `bytes`.
+    fn decode(self, bytes: &[u8], count: usize, little_endian: bool) -> Result<Vec<f32>, String> {
+        let size = self.size();
+        let len = count
+            .checked_mul(size)
+            .ok_or_else(|| format!("{} values of {} bytes do not fit in memory", count, size))?;
+        if bytes.len() < len {
+            return Err(format!(
+                "truncated data, {} bytes for {} values of {} bytes",
+                bytes.len(),
+                count,
+                size
+            ));
+        }
+        let values = bytes[..len].chunks_exact(size).map(|chunk| {
+            let mut raw = [0u8; 8];
+            raw[..size].copy_from_slice(chunk);
+            if !little_endian {
+                raw[..size].reverse();
+            }
+            match self {
+                Dtype::F16 => f16::from_le_bytes([raw[0], raw[1]]).to_f32(),
+                Dtype::Bf16 => bf16::from_le_bytes([raw[0], raw[1]]).to_f32(),
+                Dtype::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]),
+                Dtype::F64 => f64::from_le_bytes(raw) as f32,
+            }
+        });
+        Ok(values.collect())
+    }
+}
+
+/// A dense array read from a voice file.
+#[derive(Debug, Clone, PartialEq)]
+struct Tensor {
+    shape: Vec<usize>,
+    data: Vec<f32>,
+}
+
+impl Tensor {
+    /// Checks the shape, `511x1x256` or `511x256` (510 rows for v1.0 packs),
+    /// and lays out the rows.
+    fn into_style_table(self) -> Result<StyleTable, String> {
+        let rows = self.shape.first().copied().unwrap_or(0);
+        let dims = element_count(&self.shape[self.shape.len().min(1)..]);
+        if !(MIN_STYLE_ROWS..=STYLE_ROWS).contains(&rows)
+            || dims != Some(STYLE_DIM)
+            || self.data.len() != rows * STYLE_DIM
+        {
+            let shape: Vec<String> = self.shape.iter().map(ToString::to_string).collect();
+            return Err(format!(
+                "expected a {}x1x{} or {}x1x{} style table, found shape {}",
+                MIN_STYLE_ROWS,
+                STYLE_DIM,
+                STYLE_ROWS,
+                STYLE_DIM,
+                shape.join("x")
+            ));
+        }
+        Ok(self
+            .data
+            .chunks_exact(STYLE_DIM)
+            .map(|row| {
+                let mut vector = [0.0; STYLE_DIM];
+                vector.copy_from_slice(row);
+                [vector]
+            })
+            .collect())
+    }
+}
+
+/// Number of values in an array of `shape`, `None` if it overflows.
+fn element_count(shape: &[usize]) -> Option<usize> {
+    shape.iter().try_fold(1usize, |count, &dim| count.checked_mul(dim))
+}
+
+fn read_zip_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Vec<u8>, String> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|e| format!("missing {}: {}", name, e))?;
+    let mut bytes = Vec::new();
+    entry
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("failed to read {}: {}", name, e))?;
+    Ok(bytes)
+}
+
+/// Reads a `.npy` array of floats in C order.
+fn read_npy(bytes: &[u8]) -> Result<Tensor, String> {
+    if !bytes.starts_with(b"\x93NUMPY") || bytes.len() < 10 {
+        return Err("not a .npy file".to_string());
+    }
+    // Version 1 has a 2-byte header length, later versions 4 bytes
+    let (header_len, start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        _ if bytes.len() >= 12 => (u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize, 12),
+        _ => return Err("truncated .npy header".to_string()),
+    };
+    let header = bytes
+        .get(start..)
+        .and_then(|rest| rest.get(..header_len))
+        .and_then(|header| std::str::from_utf8(header).ok())
+        .ok_or("invalid .npy header")?;
+
+    let descr = header_value(header, "descr")
+        .and_then(|value| value.strip_prefix('\''))
+        .and_then(|value| value.split('\'').next())
+        .ok_or("missing dtype in .npy header")?;
+    let (little_endian, dtype) = match descr.split_at(1) {
+        ("<" | "=" | "|", ty) => (true, ty),
+        (">", ty) => (false, ty),
+        _ => (true, descr),
+    };
+    let dtype = match dtype {
+        "f2" => Dtype::F16,
+        "f4" => Dtype::F32,
+        "f8" => Dtype::F64,
+        _ => return Err(format!("unsupported .npy dtype '{}', expected floats", descr)),
+    };
+    if header_value(header, "fortran_order").is_some_and(|value| value.starts_with("True")) {
+        return Err("unsupported Fortran-ordered .npy array".to_string());
+    }
+    let shape = header_value(header, "shape")
+        .and_then(|value| value.strip_prefix('('))
+        .and_then(|value| value.split(')').next())
+        .ok_or("missing shape in .npy header")?;
+    let shape = shape
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse::<usize>().map_err(|_| format!("invalid .npy shape ({})", shape)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let count = element_count(&shape).ok_or_else(|| format!("invalid .npy shape {:?}, too many values", shape))?;
+    let data = dtype.decode(&bytes[start + header_len..], count, little_endian)?;
+    Ok(Tensor { shape, data })
+}
+
+/// The value after `'key':` in a `.npy` header dictionary.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let pattern = format!("'{}':", key);
+    let at = header.find(&pattern)?;
+    Some(header[at + pattern.len()..].trim_start())
+}
+
+/// Reads every `.npy` array of a `.npz` archive, named after its entry.
+fn read_npz(bytes: &[u8]) -> Result<Vec<(String, StyleTable)>, String> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|e| format!("not an .npz archive: {}", e))?;
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| name.ends_with(".npy"))
+        .map(str::to_string)
+        .collect();
+    names.sort();
+    if names.is_empty() {
+        return Err("no .npy arrays in the archive".to_string());
+    }
+
+    names
+        .into_iter()
+        .map(|name| {
+            let bytes = read_zip_entry(&mut archive, &name)?;
+            let table = read_npy(&bytes)
+                .and_then(Tensor::into_style_table)
+                .map_err(|e| format!("{}: {}", name, e))?;
+            Ok((name.trim_end_matches(".npy").to_string(), table))
+        })
+        .collect()
+}
+
+/// Reads `{"voice": [[[f32; 256]; 1]; 511], ...}`, or 510 rows.
+fn read_json(bytes: &[u8]) -> Result<Vec<(String, StyleTable)>, String> {
+    let value: Value = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+    let voices = value.as_object().ok_or("expected an object of voices")?;
+    voices
+        .iter()
+        .map(|(name, value)| {
+            let mut tensor = Tensor {
+                shape: Vec::new(),
+                data: Vec::new(),
+            };
+            flatten_json(value, 0, &mut tensor).map_err(|e| format!("{}: {}", name, e))?;
+            let table = tensor.into_style_table().map_err(|e| format!("{}: {}", name, e))?;
+            Ok((name.clone(), table))
+        })
+        .collect()
+}
+
+/// Appends the numbers of nested arrays to `tensor`, taking its shape from
+/// the first array at each depth.
+fn flatten_json(value: &Value, depth: usize, tensor: &mut Tensor) -> Result<(), String> {
+    match value {
+        Value::Array(items) => {
+            if tensor.shape.len() == depth {
+                tensor.shape.push(items.len());
+            }
+            items.iter().try_for_each(|item| flatten_json(item, depth + 1, tensor))
+        }
+        Value::Number(number) => {
+            tensor.data.push(number.as_f64().unwrap_or_default() as f32);
+            Ok(())
+        }
+        other => Err(format!("expected numbers, found {}", other)),
+    }
+}
+
+/// Reads a tensor saved by `torch.save`, a zip archive holding a pickle
+/// that describes the tensor and a file with its storage.
+fn read_pt(bytes: &[u8]) -> Result<Tensor, String> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))
+        .map_err(|e| format!("not a PyTorch zip archive, as saved since PyTorch 1.6: {}", e))?;
+    let pickle_name = archive
+        .file_names()
+        .find(|name| name.ends_with("data.pkl"))
+        .map(str::to_string)
+        .ok_or("missing data.pkl")?;
+    let prefix = pickle_name.trim_end_matches("data.pkl").to_string();
+
+    let pickle = read_zip_entry(&mut archive, &pickle_name)?;
+    let tensor = match unpickle(&pickle)? {
+        Pickled::Tensor(tensor) => tensor,
+        other => return Err(format!("expected a tensor, found {:?}", other)),
+    };
+    let little_endian = match read_zip_entry(&mut archive, &format!("{}byteorder", prefix)) {
+        Ok(order) => order.trim_ascii() != b"big",
+        Err(_) => true,
+    };
+
+    // Only contiguous tensors, dimensions of size 1 can have any stride
+    let mut expected = 1;
+    for (&dim, &stride) in tensor.shape.iter().zip(&tensor.stride).rev() {
+        if dim != 1 && stride != expected {
+            return Err(format!("unsupported non-contiguous tensor with strides {:?}", tensor.stride));
+        }
+        expected = expected
+            .checked_mul(dim)
+            .ok_or_else(|| format!("invalid tensor shape {:?}, too many values", tensor.shape))?;
+    }
+
+    let storage = read_zip_entry(&mut archive, &format!("{}data/{}", prefix, tensor.storage))?;
+    let start = tensor.offset.checked_mul(tensor.dtype.size());
+    let data = tensor.dtype.decode(
+        start.and_then(|start| storage.get(start..)).unwrap_or_default(),
+        expected,
+        little_endian,
+    )?;
+    Ok(Tensor {
+        shape: tensor.shape,
+        data,
+    })
+}
+
+/// Where `torch._utils._rebuild_tensor_v2` finds a tensor in its storage.
+#[derive(Debug, Clone, PartialEq)]
+struct TensorRef {
+    dtype: Dtype,
+    storage: String,
+    offset: usize,
+    shape: Vec<usize>,
+    stride: Vec<usize>,
+}
+
+/// Values of the pickle machine, only what a saved tensor is made of.
+#[derive(Debug, Clone, PartialEq)]
+enum Pickled {
+    None,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Tuple(Vec<Pickled>),
+    /// A class or function, as `module.name`.
+    Global(String),
+    Storage { dtype: Dtype, key: String },
+    Tensor(TensorRef),
+    /// Anything else, such as the empty hooks dictionary.
+    Object,
+}
+
+impl Pickled {
+    fn as_usize(&self) -> Option<usize> {
+        match self {
+            Pickled::Int(value) => usize::try_from(*value).ok(),
+            _ => None,
+        }
+    }
+
+    fn as_dims(&self) -> Option<Vec<usize>> {
+        match self {
+            Pickled::Tuple(items) => items.iter().map(Pickled::as_usize).collect(),
+            _ => None,
+        }
+    }
+}
+
+/// Runs the pickle of a saved tensor. Only the opcodes `torch.save` writes
+/// are known, and classes are recognized by name, nothing is imported or
+/// called, so a file cannot run code.
+fn unpickle(bytes: &[u8]) -> Result<Pickled, String> {
+    let mut reader = PickleReader { bytes, pos: 0 };
+    let mut stack: Vec<Pickled> = Vec::new();
+    let mut marks: Vec<usize> = Vec::new();
+    let mut memo: HashMap<usize, Pickled> = HashMap::new();
+
+    let pop = |stack: &mut Vec<Pickled>| stack.pop().ok_or_else(|| "pickle stack underflow".to_string());
+    let pop_mark = |stack: &mut Vec<Pickled>, marks: &mut Vec<usize>| {
+        let mark = marks.pop().ok_or("pickle mark missing")?;
+        Ok::<_, String>(stack.split_off(mark.min(stack.len())))
+    };
+
+    loop {
+        let op = reader.byte()?;
+        match op {
+            // PROTO, FRAME
+            0x80 => drop(reader.take(1)?),
+            0x95 => drop(reader.take(8)?),
+            // GLOBAL
+            b'c' => {
+                let module = reader.line()?;
+                let name = reader.line()?;
+                stack.push(Pickled::Global(format!("{}.{}", module, name)));
+            }
+            // STACK_GLOBAL
+            0x93 => match (pop(&mut stack)?, pop(&mut stack)?) {
+                (Pickled::Str(name), Pickled::Str(module)) => stack.push(Pickled::Global(format!("{}.{}", module, name))),
+                _ => return Err("invalid STACK_GLOBAL".to_string()),
+            },
+            // BINPUT, LONG_BINPUT, MEMOIZE
+            b'q' | b'r' | 0x94 => {
+                let key = match op {
+                    b'q' => reader.uint(1)?,
+                    b'r' => reader.uint(4)?,
+                    _ => memo.len(),
+                };
+                let top = stack.last().cloned().ok_or("pickle stack underflow")?;
+                memo.insert(key, top);
+            }
+            // BINGET, LONG_BINGET
+            b'h' | b'j' => {
+                let key = reader.uint(if op == b'h' { 1 } else { 4 })?;
+                stack.push(memo.get(&key).cloned().ok_or("pickle memo key missing")?);
+            }
+            b'(' => marks.push(stack.len()),
+            b't' => {
+                let items = pop_mark(&mut stack, &mut marks)?;
+                stack.push(Pickled::Tuple(items));
+            }
+            b')' => stack.push(Pickled::Tuple(Vec::new())),
+            // TUPLE1, TUPLE2, TUPLE3
+            0x85..=0x87 => {
+                let len = (op - 0x84) as usize;
+                if stack.len() < len {
+                    return Err("pickle stack underflow".to_string());
+                }
+                let items = stack.split_off(stack.len() - len);
+                stack.push(Pickled::Tuple(items));
+            }
+            // BINUNICODE, SHORT_BINUNICODE
+            b'X' | 0x8c => {
+                let len = reader.uint(if op == b'X' { 4 } else { 1 })?;
+                let text = String::from_utf8(reader.take(len)?.to_vec()).map_err(|_| "invalid pickle string")?;
+                stack.push(Pickled::Str(text));
+            }
+            // BININT, BININT1, BININT2, LONG1
+            b'J' => {
+                let raw = reader.take(4)?;
+                stack.push(Pickled::Int(i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as i64));
+            }
+            b'K' => stack.push(Pickled::Int(reader.uint(1)? as i64)),
+            b'M' => stack.push(Pickled::Int(reader.uint(2)? as i64)),
+            0x8a => {
+                let len = reader.uint(1)?;
+                let raw = reader.take(len)?;
+                if len > 8 {
+                    return Err("pickle integer too large".to_string());
+                }
+                let mut value = [if raw.last().is_some_and(|b| b & 0x80 != 0) { 0xff } else { 0 }; 8];
+                value[..len].copy_from_slice(raw);
+                stack.push(Pickled::Int(i64::from_le_bytes(value)));
+            }
+            0x88 => stack.push(Pickled::Bool(true)),
+            0x89 => stack.push(Pickled::Bool(false)),
+            b'N' => stack.push(Pickled::None),
+            // BINPERSID, the storage as ('storage', type, key, device, size)
+            b'Q' => {
+                let pid = pop(&mut stack)?;
+                stack.push(storage(&pid)?);
+            }
+            // REDUCE
+            b'R' => {
+                let args = pop(&mut stack)?;
+                let callable = pop(&mut stack)?;
+                stack.push(match callable {
+                    Pickled::Global(name) if name == "torch._utils._rebuild_tensor_v2" => rebuild_tensor(&args)?,
+                    _ => Pickled::Object,
+                });
+            }
+            // EMPTY_DICT, EMPTY_LIST
+            b'}' | b']' => stack.push(Pickled::Object),
+            // SETITEMS, APPENDS
+            b'u' | b'e' => drop(pop_mark(&mut stack, &mut marks)?),
+            // SETITEM, APPEND, BUILD
+            b's' => drop((pop(&mut stack)?, pop(&mut stack)?)),
+            b'a' | b'b' => drop(pop(&mut stack)?),
+            b'.' => return pop(&mut stack),
+            _ => return Err(format!("unsupported pickle opcode 0x{:02x}", op)),
+        }
+    }
+}
+
+fn storage(pid: &Pickled) -> Result<Pickled, String> {
+    let Pickled::Tuple(items) = pid else {
+        return Err("invalid storage reference".to_string());
+    };
+    match items.as_slice() {
+        [Pickled::Str(kind), Pickled::Global(class), Pickled::Str(key), ..] if kind == "storage" => {
+            let dtype = match class.as_str() {
+                "torch.HalfStorage" => Dtype::F16,
+                "torch.BFloat16Storage" => Dtype::Bf16,
+                "torch.FloatStorage" => Dtype::F32,
+                "torch.DoubleStorage" => Dtype::F64,
+                _ => return Err(format!("unsupported tensor storage {}, expected floats", class)),
+            };
+            Ok(Pickled::Storage {
+                dtype,
+                key: key.clone(),
+            })
+        }
+        _ => Err("invalid storage reference".to_string()),
+    }
+}
+
+/// `_rebuild_tensor_v2(storage, offset, shape, stride, ...)`.
+fn rebuild_tensor(args: &Pickled) -> Result<Pickled, String> {
+    let Pickled::Tuple(items) = args else {
+        return Err("invalid tensor arguments".to_string());
+    };
+    match items.as_slice() {
+        [Pickled::Storage { dtype, key }, offset, shape, stride, ..] => Ok(Pickled::Tensor(TensorRef {
+            dtype: *dtype,
+            storage: key.clone(),
+            offset: offset.as_usize().ok_or("invalid tensor offset")?,
+            shape: shape.as_dims().ok_or("invalid tensor shape")?,
+            stride: stride.as_dims().ok_or("invalid tensor stride")?,
+        })),
+        _ => Err("invalid tensor arguments".to_string()),
+    }
+}
+
+struct PickleReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PickleReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or("truncated pickle")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn uint(&mut self, len: usize) -> Result<usize, String> {
+        let mut value = [0u8; 8];
+        value[..len].copy_from_slice(self.take(len)?);
+        Ok(u64::from_le_bytes(value) as usize)
+    }
+
+    fn line(&mut self) -> Result<String, String> {
+        let rest = &self.bytes[self.pos..];
+        let end = rest.iter().position(|&b| b == b'\n').ok_or("truncated pickle")?;
+        self.pos += end + 1;
+        Ok(String::from_utf8_lossy(&rest[..end]).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::temp_dir;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    /// A `.npy` file of `shape` holding `0, 1, 2, ...` as f32.
+    fn npy(shape: &[usize]) -> Vec<u8> {
+        let dims: Vec<String> = shape.iter().map(ToString::to_string).collect();
+        let trailing = if shape.len() == 1 { "," } else { "" };
+        let mut header = format!(
+            "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}{}), }}",
+            dims.join(", "),
+            trailing
+        );
+        while (10 + header.len() + 1) % 64 != 0 {
+            header.push(' ');
+        }
+        header.push('\n');
+
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        for i in 0..shape.iter().product::<usize>() {
+            bytes.extend((i as f32).to_le_bytes());
+        }
+        bytes
+    }
+
+    fn npz(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, bytes) in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(bytes).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_read_npy() {
+        let table = read_npy(&npy(&[STYLE_ROWS, 1, STYLE_DIM])).unwrap().into_style_table().unwrap();
+        assert_eq!(table.len(), STYLE_ROWS);
+        assert_eq!(table[0][0][1], 1.0);
+        assert_eq!(table[2][0][0], (2 * STYLE_DIM) as f32);
+    }
+
+    #[test]
+    fn test_read_v1_npy() {
+        let table = read_npy(&npy(&[MIN_STYLE_ROWS, 1, STYLE_DIM])).unwrap().into_style_table().unwrap();
+        assert_eq!(table.len(), MIN_STYLE_ROWS);
+
+        let err = read_npy(&npy(&[STYLE_ROWS + 1, 1, STYLE_DIM])).unwrap().into_style_table().unwrap_err();
+        assert_eq!(err, "expected a 510x1x256 or 511x1x256 style table, found shape 512x1x256");
+    }
+
+    #[test]
+    fn test_wrong_shape_is_rejected() {
+        let err = read_npy(&npy(&[10, 256])).unwrap().into_style_table().unwrap_err();
+        assert_eq!(err, "expected a 510x1x256 or 511x1x256 style table, found shape 10x256");
+    }
+
+    #[test]
+    fn test_overflowing_shape_is_rejected() {
+        let npy_with_shape = |shape: &str| {
+            let header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({}), }}\n", shape);
+            let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+            bytes.extend((header.len() as u16).to_le_bytes());
+            bytes.extend(header.as_bytes());
+            bytes
+        };
+        let err = read_npy(&npy_with_shape("4294967296, 4294967296, 4294967296")).unwrap_err();
+        assert!(err.ends_with("too many values"), "{}", err);
+        let err = read_npy(&npy_with_shape("4611686018427387904,")).unwrap_err();
+        assert!(err.ends_with("do not fit in memory"), "{}", err);
+    }
+
+    #[test]
+    fn test_read_npz() {
+        let table = npy(&[STYLE_ROWS, 1, STYLE_DIM]);
+        let voices = read_npz(&npz(&[("bf_emma.npy", table.clone()), ("af.npy", table)])).unwrap();
+        let names: Vec<&str> = voices.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["af", "bf_emma"]);
+    }
+
+    #[test]
+    fn test_read_json() {
+        let table = vec![vec![vec![0.5; STYLE_DIM]; 1]; STYLE_ROWS];
+        let json = serde_json::json!({ "af": table, "bad": [[["x"]]] });
+        let err = read_json(json.to_string().as_bytes()).unwrap_err();
+        assert_eq!(err, "bad: expected numbers, found \"x\"");
+
+        let json = serde_json::json!({ "af": table });
+        let voices = read_json(json.to_string().as_bytes()).unwrap();
+        assert_eq!(voices[0].0, "af");
+        assert_eq!(voices[0].1[STYLE_ROWS - 1][0][STYLE_DIM - 1], 0.5);
+    }
+
+    #[test]
+    fn test_read_shipped_pt() {
+        let voices = load_voice_file(Path::new("data/af.pt")).unwrap();
+        assert_eq!(voices.len(), 1);
+        let (name, table) = &voices[0];
+        assert_eq!(name, "af");
+        assert_eq!(table.len(), STYLE_ROWS);
+        assert!(table.iter().flatten().flatten().all(|v| v.is_finite()));
+        assert!(table[0][0].iter().any(|v| *v != 0.0));
+    }
+
+    #[test]
+    fn test_pickle_cannot_call_other_globals() {
+        // os.system('x') as a pickle
+        let pickle = b"\x80\x02cos\nsystem\nX\x01\x00\x00\x00x\x85R.";
+        assert_eq!(unpickle(pickle), Ok(Pickled::Object));
+    }
+
+    #[test]
+    fn test_load_directory_reports_every_failing_file() {
+        let dir = temp_dir("voices");
+        fs::write(dir.join("good.npy"), npy(&[STYLE_ROWS, 1, STYLE_DIM])).unwrap();
+        fs::write(dir.join("short.npy"), npy(&[3, 1, STYLE_DIM])).unwrap();
+        fs::write(dir.join("broken.pt"), b"not a zip").unwrap();
+        fs::write(dir.join("notes.txt"), b"ignored").unwrap();
+
+        let err = load_voices(&dir).unwrap_err();
+        assert!(err.starts_with("failed to load 2 of 3 voice files"), "{}", err);
+        assert!(err.contains("broken.pt: not a PyTorch zip archive"), "{}", err);
+        assert!(err.contains("short.npy: expected a 510x1x256 or 511x1x256 style table, found shape 3x1x256"), "{}", err);
+
+        fs::remove_file(dir.join("short.npy")).unwrap();
+        fs::remove_file(dir.join("broken.pt")).unwrap();
+        let voices = load_voices(&dir).unwrap();
+        assert_eq!(voices.keys().collect::<Vec<_>>(), ["good"]);
+    }
+
+    #[test]
+    fn test_has_voice_files() {
+        assert!(has_voice_files(Path::new(SHIPPED_VOICES_DIR)));
+        let dir = temp_dir("voices-none");
+        fs::write(dir.join("notes.txt"), b"ignored").unwrap();
+        assert!(!has_voice_files(&dir));
+        assert!(!has_voice_files(&dir.join("missing")));
+    }
+
+    #[test]
+    fn test_missing_file_is_an_error() {
+        let err = load_voices(Path::new("data/missing.json")).unwrap_err();
+        assert!(err.starts_with("failed to load voices from data/missing.json"), "{}", err);
+    }
+}
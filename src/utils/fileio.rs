@@ -1,24 +1,87 @@
 use std::{
-    fs::File,
+    fs::{self, File, OpenOptions},
     io::{Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
+use hmac_sha256::Hash;
 use indicatif::{ProgressBar, ProgressStyle};
 use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use reqwest::StatusCode;
 use serde_json::Value;
 
-pub fn download_file_from_url(url: &str, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(parent) = Path::new(path).parent() {
-        std::fs::create_dir_all(parent)?;
+/// Where a download is written until it is complete.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Downloads `url` to `path`, returning the SHA-256 of the file.
+///
+/// Bytes go to `<path>.part` first, which is renamed to `path` only once
+/// the download is complete and, when `expected_sha256` is given, matches
+/// it. An interrupted download is resumed from the `.part` file with an
+/// HTTP Range request, a file that fails verification is deleted.
+pub fn download_verified(url: &str, path: &Path, expected_sha256: Option<&str>) -> Result<String, String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
     }
+    let part = part_path(path);
+    let mut offset = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
 
     let client = Client::new();
-    let mut resp = client.get(url).send()?;
+    let mut request = client.get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let mut resp = request.send().map_err(|e| format!("failed to download {}: {}", url, e))?;
 
-    if resp.status().is_success() {
-        let total_size = resp.content_length().unwrap_or(0);
+    let status = resp.status();
+    let range = resp
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_content_range);
+    let complete = if status == StatusCode::RANGE_NOT_SATISFIABLE && offset > 0 {
+        // Nothing left to fetch only if the part is exactly the whole file,
+        // it is then checked below
+        if range.and_then(|r| r.total) != Some(offset) {
+            println!("The partial download does not match the file, starting over");
+            return restart(url, path, expected_sha256);
+        }
+        true
+    } else if status == StatusCode::PARTIAL_CONTENT {
+        if range.and_then(|r| r.start) != Some(offset) {
+            println!("The server did not resume at {} bytes, starting over", offset);
+            return restart(url, path, expected_sha256);
+        }
+        println!("Resuming download at {} bytes", offset);
+        false
+    } else if status.is_success() {
+        // The server ignored the range, start over
+        offset = 0;
+        false
+    } else {
+        return Err(format!("failed to download {}: {}", url, status));
+    };
 
+    let html = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+    if !complete && html {
+        return Err(format!(
+            "{} returned a web page instead of the file, check the URL or mirror",
+            url
+        ));
+    }
+
+    if !complete {
+        let expected_len = resp.content_length();
+        let total_size = offset + expected_len.unwrap_or(0);
         println!("total size: {}", total_size);
 
         let pb = ProgressBar::new(total_size);
@@ -26,26 +89,107 @@ pub fn download_file_from_url(url: &str, path: &str) -> Result<(), Box<dyn std::
             .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
             .unwrap()
             .progress_chars("#>-"));
+        pb.set_position(offset);
 
-        let mut file = File::create(path)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(offset > 0)
+            .truncate(offset == 0)
+            .open(&part)
+            .map_err(|e| format!("failed to open {}: {}", part.display(), e))?;
         let mut buffer = [0; 8192]; // 8KB buffer
         let mut downloaded = 0;
 
         loop {
-            let bytes_read = resp.read(&mut buffer)?;
-            if bytes_read == 0 {
-                break;
-            }
-            file.write_all(&buffer[..bytes_read])?;
+            let bytes_read = match resp.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => {
+                    pb.abandon();
+                    return Err(format!(
+                        "download of {} stopped after {} bytes ({}), run again to resume",
+                        url,
+                        offset + downloaded,
+                        e
+                    ));
+                }
+            };
+            file.write_all(&buffer[..bytes_read])
+                .map_err(|e| format!("failed to write {}: {}", part.display(), e))?;
             downloaded += bytes_read as u64;
-            pb.set_position(downloaded);
+            pb.set_position(offset + downloaded);
         }
+        file.sync_all().map_err(|e| format!("failed to write {}: {}", part.display(), e))?;
 
+        if expected_len.is_some_and(|len| downloaded < len) {
+            pb.abandon();
+            return Err(format!(
+                "download of {} stopped after {} of {} bytes, run again to resume",
+                url,
+                offset + downloaded,
+                total_size
+            ));
+        }
         pb.finish_with_message("Download completed");
-        Ok(())
-    } else {
-        Err(format!("Failed to download file: {}", resp.status()).into())
     }
+
+    let sha256 = sha256_file(&part)?;
+    if let Some(expected) = expected_sha256 {
+        if !sha256.eq_ignore_ascii_case(expected) {
+            let _ = fs::remove_file(&part);
+            return Err(format!(
+                "checksum mismatch for {}: expected sha256 {}, got {}, the download was deleted",
+                url, expected, sha256
+            ));
+        }
+    }
+    fs::rename(&part, path).map_err(|e| format!("failed to move {} into place: {}", part.display(), e))?;
+    Ok(sha256)
+}
+
+/// Deletes the partial download of `path` and downloads it from the start.
+fn restart(url: &str, path: &Path, expected_sha256: Option<&str>) -> Result<String, String> {
+    let part = part_path(path);
+    fs::remove_file(&part).map_err(|e| format!("failed to delete {}: {}", part.display(), e))?;
+    download_verified(url, path, expected_sha256)
+}
+
+/// A `Content-Range` header, `bytes <start>-<end>/<total>` or
+/// `bytes */<total>`, with an unknown total given as `*`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ContentRange {
+    start: Option<u64>,
+    total: Option<u64>,
+}
+
+fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let (range, total) = value.trim().strip_prefix("bytes")?.trim().split_once('/')?;
+    let start = match range.trim() {
+        "*" => None,
+        range => Some(range.split_once('-')?.0.trim().parse().ok()?),
+    };
+    Some(ContentRange {
+        start,
+        total: total.trim().parse().ok(),
+    })
+}
+
+/// SHA-256 of the file at `path`, in lowercase hex.
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+    let mut hash = Hash::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let bytes_read = file
+            .read(&mut buffer)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        if bytes_read == 0 {
+            break;
+        }
+        hash.update(&buffer[..bytes_read]);
+    }
+    Ok(hash.finalize().iter().map(|b| format!("{:02x}", b)).collect())
 }
 
 pub fn load_json_file(path: &str) -> Result<Value, String> {
@@ -62,3 +206,137 @@ pub fn load_json_file(path: &str) -> Result<Value, String> {
 
     Ok(json_value)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{serve, serve_misaligned, temp_dir};
+
+    fn body() -> Vec<u8> {
+        (0..50_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_download_is_renamed_when_complete() {
+        let dir = temp_dir("fileio-complete");
+        let server = serve(body(), None);
+        let path = dir.join("model.onnx");
+
+        let sha256 = download_verified(&server.url, &path, None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), body());
+        assert_eq!(sha256, sha256_file(&path).unwrap());
+        assert!(!part_path(&path).exists());
+    }
+
+    #[test]
+    fn test_interrupted_download_resumes() {
+        let dir = temp_dir("fileio-resume");
+        let server = serve(body(), Some(20_000));
+        let path = dir.join("model.onnx");
+
+        let err = download_verified(&server.url, &path, None).unwrap_err();
+        assert!(err.contains("run again to resume"), "{}", err);
+        assert!(!path.exists());
+        assert_eq!(fs::metadata(part_path(&path)).unwrap().len(), 20_000);
+
+        download_verified(&server.url, &path, None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), body());
+        assert_eq!(
+            *server.ranges.lock().unwrap(),
+            vec![None, Some("bytes=20000-".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_complete_part_file_is_verified() {
+        let dir = temp_dir("fileio-416");
+        let server = serve(body(), None);
+        let path = dir.join("model.onnx");
+        fs::write(part_path(&path), body()).unwrap();
+        let expected = sha256_file(&part_path(&path)).unwrap();
+
+        assert_eq!(download_verified(&server.url, &path, Some(&expected)).unwrap(), expected);
+        assert_eq!(fs::read(&path).unwrap(), body());
+    }
+
+    #[test]
+    fn test_oversized_part_file_restarts() {
+        let dir = temp_dir("fileio-oversized");
+        let server = serve(body(), None);
+        let path = dir.join("model.onnx");
+        let mut part = body();
+        part.extend_from_slice(b"trailing garbage");
+        fs::write(part_path(&path), part).unwrap();
+
+        download_verified(&server.url, &path, None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), body());
+        assert_eq!(
+            *server.ranges.lock().unwrap(),
+            vec![Some(format!("bytes={}-", body().len() + 16)), None]
+        );
+    }
+
+    #[test]
+    fn test_misaligned_resume_restarts() {
+        let dir = temp_dir("fileio-misaligned");
+        let server = serve_misaligned(body());
+        let path = dir.join("model.onnx");
+        fs::write(part_path(&path), &body()[..20_000]).unwrap();
+
+        download_verified(&server.url, &path, None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), body());
+        assert_eq!(
+            *server.ranges.lock().unwrap(),
+            vec![Some("bytes=20000-".to_string()), None]
+        );
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 100-199/200"),
+            Some(ContentRange {
+                start: Some(100),
+                total: Some(200)
+            })
+        );
+        assert_eq!(
+            parse_content_range("bytes */200"),
+            Some(ContentRange {
+                start: None,
+                total: Some(200)
+            })
+        );
+        assert_eq!(
+            parse_content_range("bytes 0-9/*"),
+            Some(ContentRange {
+                start: Some(0),
+                total: None
+            })
+        );
+        assert_eq!(parse_content_range("items 0-9/10"), None);
+    }
+
+    #[test]
+    fn test_checksum_mismatch_deletes_download() {
+        let dir = temp_dir("fileio-mismatch");
+        let server = serve(body(), None);
+        let path = dir.join("model.onnx");
+
+        let err = download_verified(&server.url, &path, Some(&"ab".repeat(32))).unwrap_err();
+        assert!(err.contains("checksum mismatch"), "{}", err);
+        assert!(!path.exists());
+        assert!(!part_path(&path).exists());
+    }
+
+    #[test]
+    fn test_sha256_file() {
+        let dir = temp_dir("fileio-sha");
+        let path = dir.join("abc.txt");
+        fs::write(&path, b"abc").unwrap();
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
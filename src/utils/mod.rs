@@ -1 +1,5 @@
 pub mod fileio;
+pub mod models;
+
+#[cfg(test)]
+pub(crate) mod test_server;
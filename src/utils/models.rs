@@ -0,0 +1,510 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::fileio::{download_verified, part_path, sha256_file};
+
+/// Where models are downloaded from unless a mirror is set.
+pub const DEFAULT_MIRROR: &str = "https://huggingface.co";
+
+/// Name of the manifest kept next to downloaded models.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// A file that can be downloaded, at `remote_path` under the mirror or,
+/// for a full URL, from there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelFile {
+    /// Short name used on the command line.
+    pub name: &'static str,
+    /// Name of the file in the model directory.
+    pub file_name: &'static str,
+    pub remote_path: &'static str,
+    /// Where the file was kept relative to the working directory before
+    /// the model directory, still used when it is there.
+    pub legacy_path: Option<&'static str>,
+    pub description: &'static str,
+    /// SHA-256 of the published file, which downloads and files already in
+    /// place must match. Without it the manifest records the first copy.
+    pub sha256: Option<&'static str>,
+}
+
+pub const KOKORO_FP32: ModelFile = ModelFile {
+    name: "fp32",
+    file_name: "kokoro-v0_19.onnx",
+    remote_path: "hexgrad/Kokoro-82M/resolve/main/kokoro-v0_19.onnx",
+    legacy_path: Some("checkpoints/kokoro-v0_19.onnx"),
+    description: "Kokoro v0.19, fp32 weights",
+    sha256: None,
+};
+
+pub const KOKORO_FP16: ModelFile = ModelFile {
+    name: "fp16",
+    file_name: "kokoro-v0_19-fp16.onnx",
+    remote_path: "onnx-community/Kokoro-82M-ONNX/resolve/main/onnx/model_fp16.onnx",
+    legacy_path: Some("checkpoints/kokoro-v0_19-fp16.onnx"),
+    description: "Kokoro v0.19, fp16 weights (onnx-community export)",
+    sha256: None,
+};
+
+pub const KOKORO_INT8: ModelFile = ModelFile {
+    name: "int8",
+    file_name: "kokoro-v0_19-int8.onnx",
+    remote_path: "onnx-community/Kokoro-82M-ONNX/resolve/main/onnx/model_quantized.onnx",
+    legacy_path: Some("checkpoints/kokoro-v0_19-int8.onnx"),
+    description: "Kokoro v0.19, 8-bit quantized (onnx-community export)",
+    sha256: None,
+};
+
+pub const KOKORO_Q4: ModelFile = ModelFile {
+    name: "q4",
+    file_name: "kokoro-v0_19-q4.onnx",
+    remote_path: "onnx-community/Kokoro-82M-ONNX/resolve/main/onnx/model_q4.onnx",
+    legacy_path: Some("checkpoints/kokoro-v0_19-q4.onnx"),
+    description: "Kokoro v0.19, 4-bit quantized (onnx-community export)",
+    sha256: None,
+};
+
+pub const VOICES: ModelFile = ModelFile {
+    name: "voices",
+    file_name: "voices.bin",
+    remote_path: "https://github.com/thewh1teagle/kokoro-onnx/releases/download/model-files/voices.bin",
+    legacy_path: None,
+    description: "Kokoro v0.19 voice styles",
+    sha256: None,
+};
+
+/// Every file the model directory may hold.
+pub const CATALOG: [ModelFile; 5] = [KOKORO_FP32, KOKORO_FP16, KOKORO_INT8, KOKORO_Q4, VOICES];
+
+/// Finds a catalog entry by its short name.
+pub fn find_file(name: &str) -> Result<&'static ModelFile, String> {
+    CATALOG.iter().find(|file| file.name == name).ok_or_else(|| {
+        let names: Vec<&str> = CATALOG.iter().map(|file| file.name).collect();
+        format!("unknown model file '{}', expected one of: {}", name, names.join(", "))
+    })
+}
+
+/// Directory models and voices are kept in: `KOKO_HOME` when set,
+/// otherwise `kokoros` in the user cache directory, `$XDG_CACHE_HOME` or
+/// `~/.cache` on Linux.
+pub fn koko_home() -> PathBuf {
+    home_from(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+}
+
+fn home_from(var: impl Fn(&str) -> Option<String>) -> PathBuf {
+    if let Some(home) = var("KOKO_HOME") {
+        return PathBuf::from(home);
+    }
+    let cache = if cfg!(target_os = "windows") {
+        var("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| Path::new(&home).join("Library/Caches"))
+    } else {
+        var("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| var("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    cache.unwrap_or_else(|| PathBuf::from(".")).join("kokoros")
+}
+
+/// Checksum and size of a model file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub sha256: String,
+    /// Size in bytes, informational and left out of hand-written entries.
+    #[serde(default)]
+    pub size: u64,
+}
+
+/// The known good checksum of every file in a model directory, by file
+/// name, e.g. `{"kokoro-v0_19.onnx": {"sha256": "..."}}`.
+///
+/// A download is checked against its entry. Files downloaded without one
+/// get an entry recorded, so later copies are held to the same bytes.
+/// Entries can also be written by hand, or the manifest copied from a
+/// trusted machine, to pin files before their first download.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Manifest {
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Manifest of the directory holding `path`.
+    pub fn path_for(path: &Path) -> PathBuf {
+        path.parent().unwrap_or(Path::new(".")).join(MANIFEST_FILE)
+    }
+
+    /// Reads a manifest, empty if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).map_err(|e| format!("invalid manifest {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("failed to read manifest {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Writes the manifest through a temporary file, so it is never left
+    /// half written.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, data).map_err(|e| format!("failed to write {}: {}", tmp.display(), e))?;
+        fs::rename(&tmp, path).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// The checksum a copy of `file` must have: the published one, otherwise
+/// the one recorded in the manifest.
+fn expected_sha256(file: &ModelFile, recorded: Option<&ManifestEntry>) -> Option<String> {
+    file.sha256
+        .map(str::to_string)
+        .or_else(|| recorded.map(|entry| entry.sha256.clone()))
+}
+
+/// What `ModelStore::verify` found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    /// The file matches its manifest entry.
+    Valid,
+    /// The file is there but has no manifest entry to compare with.
+    Unrecorded { sha256: String },
+    Mismatch { expected: String, actual: String },
+    Missing,
+}
+
+/// Fetches missing model files and checks them against the manifest.
+#[derive(Debug, Clone)]
+pub struct ModelStore {
+    dir: PathBuf,
+    mirror: String,
+    offline: bool,
+}
+
+impl Default for ModelStore {
+    fn default() -> Self {
+        Self {
+            dir: koko_home(),
+            mirror: DEFAULT_MIRROR.to_string(),
+            offline: false,
+        }
+    }
+}
+
+impl ModelStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Downloads from `mirror` instead of Hugging Face, which must serve
+    /// files under the same paths.
+    pub fn with_mirror(mut self, mirror: &str) -> Self {
+        self.mirror = mirror.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Never touches the network, missing files are an error.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn url(&self, file: &ModelFile) -> String {
+        if file.remote_path.contains("://") {
+            return file.remote_path.to_string();
+        }
+        format!("{}/{}", self.mirror, file.remote_path)
+    }
+
+    /// Where `file` is used from: its legacy location if it is there,
+    /// otherwise the model directory.
+    pub fn path_for(&self, file: &ModelFile) -> PathBuf {
+        match self.legacy_path(file) {
+            Some(legacy) => legacy,
+            None => self.stored_path(file),
+        }
+    }
+
+    /// Where `file` is kept in the model directory, the only place
+    /// `koko models` downloads to or deletes from.
+    pub fn stored_path(&self, file: &ModelFile) -> PathBuf {
+        self.dir.join(file.file_name)
+    }
+
+    /// The legacy location of `file`, if a copy is there.
+    pub fn legacy_path(&self, file: &ModelFile) -> Option<PathBuf> {
+        file.legacy_path.map(PathBuf::from).filter(|legacy| legacy.exists())
+    }
+
+    /// Makes sure `file` is at `path`, downloading it if it is missing.
+    ///
+    /// A file already there is checked the first time it is used, as it
+    /// may have been left by an older version that wrote downloads in
+    /// place. Once checked, its manifest entry records it, and only its
+    /// size is compared after that.
+    pub fn ensure(&self, file: &ModelFile, path: &Path) -> Result<(), String> {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| format!("invalid model path {}", path.display()))?;
+        let manifest_path = Manifest::path_for(path);
+        let mut manifest = Manifest::load(&manifest_path)?;
+        let recorded = manifest.files.get(name).cloned();
+        let expected = expected_sha256(file, recorded.as_ref());
+
+        if path.exists() {
+            let size = fs::metadata(path).map_err(|e| e.to_string())?.len();
+            let checked = recorded.as_ref().is_some_and(|entry| {
+                entry.size == size && expected.as_deref().is_some_and(|sha| sha.eq_ignore_ascii_case(&entry.sha256))
+            });
+            if checked {
+                return Ok(());
+            }
+
+            println!("Checking the sha256 of {}", path.display());
+            let sha256 = sha256_file(path)?;
+            if let Some(expected) = expected.filter(|sha| !sha.eq_ignore_ascii_case(&sha256)) {
+                return Err(format!(
+                    "{} is corrupt: expected sha256 {}, got {}, delete it and run again to download it",
+                    path.display(),
+                    expected,
+                    sha256
+                ));
+            }
+            manifest.files.insert(name.to_string(), ManifestEntry { sha256, size });
+            return manifest.save(&manifest_path);
+        }
+        if self.offline {
+            return Err(format!(
+                "{} is missing and offline mode is on, download it first without --offline",
+                path.display()
+            ));
+        }
+
+        let url = self.url(file);
+        println!("Downloading {} from {}", path.display(), url);
+        let sha256 = download_verified(&url, path, expected.as_deref())?;
+        match expected {
+            Some(_) => println!("✓ {} matches its sha256", name),
+            None => println!(
+                "! {} has no published sha256, recorded its sha256 in {} so later copies must match it",
+                name,
+                manifest_path.display()
+            ),
+        }
+        let size = fs::metadata(path).map_err(|e| e.to_string())?.len();
+        manifest.files.insert(name.to_string(), ManifestEntry { sha256, size });
+        manifest.save(&manifest_path)
+    }
+
+    /// Checks the file at `path` against the published checksum of `file`,
+    /// or else the manifest of its directory.
+    pub fn verify(&self, file: &ModelFile, path: &Path) -> Result<Verification, String> {
+        if !path.exists() {
+            return Ok(Verification::Missing);
+        }
+        let manifest = Manifest::load(&Manifest::path_for(path))?;
+        let actual = sha256_file(path)?;
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        Ok(match expected_sha256(file, manifest.files.get(name)) {
+            None => Verification::Unrecorded { sha256: actual },
+            Some(expected) if expected.eq_ignore_ascii_case(&actual) => Verification::Valid,
+            Some(expected) => Verification::Mismatch { expected, actual },
+        })
+    }
+
+    /// Deletes the file at `path`, any partial download of it and its
+    /// manifest entry. Returns whether there was anything to delete.
+    pub fn remove(&self, path: &Path) -> Result<bool, String> {
+        let mut removed = false;
+        for file in [path.to_path_buf(), part_path(path)] {
+            if file.exists() {
+                fs::remove_file(&file).map_err(|e| format!("failed to delete {}: {}", file.display(), e))?;
+                removed = true;
+            }
+        }
+
+        let manifest_path = Manifest::path_for(path);
+        let mut manifest = Manifest::load(&manifest_path)?;
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        if manifest.files.remove(name).is_some() {
+            manifest.save(&manifest_path)?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fileio::sha256_file;
+    use crate::utils::test_server::{serve, temp_dir};
+
+    const FILE: ModelFile = ModelFile {
+        name: "test",
+        file_name: "kokoro.onnx",
+        remote_path: "model.onnx",
+        legacy_path: None,
+        description: "test model",
+        sha256: None,
+    };
+
+    /// `FILE` with the published checksum of `b"weights"`.
+    const PUBLISHED: ModelFile = ModelFile {
+        sha256: Some("9a129038d9a00aed0cf6a7ea059ca50a813449061ab87848cf1a13eafdf33b2c"),
+        ..FILE
+    };
+
+    fn store_for(url: &str) -> ModelStore {
+        ModelStore::new().with_mirror(url.trim_end_matches("model.onnx"))
+    }
+
+    #[test]
+    fn test_home_dir() {
+        let vars = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| pairs.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+        };
+        assert_eq!(
+            home_from(vars(&[("KOKO_HOME", "/opt/koko"), ("HOME", "/home/me")])),
+            PathBuf::from("/opt/koko")
+        );
+        if cfg!(target_os = "linux") {
+            assert_eq!(
+                home_from(vars(&[("XDG_CACHE_HOME", "/tmp/cache"), ("HOME", "/home/me")])),
+                PathBuf::from("/tmp/cache/kokoros")
+            );
+            assert_eq!(home_from(vars(&[("HOME", "/home/me")])), PathBuf::from("/home/me/.cache/kokoros"));
+        }
+    }
+
+    #[test]
+    fn test_verify_and_remove() {
+        let dir = temp_dir("models-verify");
+        let server = serve(b"weights".to_vec(), None);
+        let store = ModelStore {
+            dir: dir.clone(),
+            ..store_for(&server.url)
+        };
+        let path = store.path_for(&FILE);
+        assert_eq!(path, dir.join("kokoro.onnx"));
+        assert_eq!(store.verify(&FILE, &path).unwrap(), Verification::Missing);
+
+        store.ensure(&FILE, &path).unwrap();
+        assert_eq!(store.verify(&FILE, &path).unwrap(), Verification::Valid);
+
+        fs::write(&path, b"corrupt").unwrap();
+        assert!(matches!(store.verify(&FILE, &path).unwrap(), Verification::Mismatch { .. }));
+
+        assert!(store.remove(&path).unwrap());
+        assert!(!path.exists());
+        assert!(Manifest::load(&dir.join(MANIFEST_FILE)).unwrap().files.is_empty());
+        assert!(!store.remove(&path).unwrap());
+    }
+
+    #[test]
+    fn test_find_file() {
+        assert_eq!(find_file("q4").unwrap().file_name, "kokoro-v0_19-q4.onnx");
+        assert!(find_file("q2").unwrap_err().contains("fp32, fp16, int8, q4, voices"));
+        assert_eq!(ModelStore::new().url(&VOICES), VOICES.remote_path);
+    }
+
+    #[test]
+    fn test_ensure_records_manifest() {
+        let dir = temp_dir("models-record");
+        let server = serve(b"weights".to_vec(), None);
+        let path = dir.join("kokoro.onnx");
+
+        store_for(&server.url).ensure(&FILE, &path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"weights");
+
+        let manifest = Manifest::load(&dir.join(MANIFEST_FILE)).unwrap();
+        let entry = &manifest.files["kokoro.onnx"];
+        assert_eq!(entry.sha256, sha256_file(&path).unwrap());
+        assert_eq!(entry.size, 7);
+    }
+
+    #[test]
+    fn test_ensure_checks_pinned_sha() {
+        let dir = temp_dir("models-pinned");
+        let server = serve(b"tampered".to_vec(), None);
+        let path = dir.join("kokoro.onnx");
+        let mut manifest = Manifest::default();
+        manifest.files.insert(
+            "kokoro.onnx".to_string(),
+            ManifestEntry {
+                sha256: "00".repeat(32),
+                size: 7,
+            },
+        );
+        manifest.save(&dir.join(MANIFEST_FILE)).unwrap();
+
+        let err = store_for(&server.url).ensure(&FILE, &path).unwrap_err();
+        assert!(err.contains("checksum mismatch"), "{}", err);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_published_sha_rejects_first_download() {
+        let dir = temp_dir("models-published");
+        let server = serve(b"<html>not found</html>".to_vec(), None);
+        let path = dir.join("kokoro.onnx");
+
+        let err = store_for(&server.url).ensure(&PUBLISHED, &path).unwrap_err();
+        assert!(err.contains("checksum mismatch"), "{}", err);
+        assert!(!path.exists());
+        assert!(Manifest::load(&dir.join(MANIFEST_FILE)).unwrap().files.is_empty());
+    }
+
+    #[test]
+    fn test_existing_file_is_checked_once() {
+        let dir = temp_dir("models-existing");
+        let server = serve(b"weights".to_vec(), None);
+        let store = store_for(&server.url).with_offline(true);
+        let path = dir.join("kokoro.onnx");
+
+        // Left truncated by a download written in place
+        fs::write(&path, b"weig").unwrap();
+        let err = store.ensure(&PUBLISHED, &path).unwrap_err();
+        assert!(err.contains("is corrupt"), "{}", err);
+
+        fs::write(&path, b"weights").unwrap();
+        store.ensure(&PUBLISHED, &path).unwrap();
+        assert_eq!(store.verify(&PUBLISHED, &path).unwrap(), Verification::Valid);
+        let manifest = Manifest::load(&dir.join(MANIFEST_FILE)).unwrap();
+        assert_eq!(manifest.files["kokoro.onnx"].size, 7);
+
+        // Checked already, only a size change is noticed
+        fs::write(&path, b"weightz").unwrap();
+        store.ensure(&PUBLISHED, &path).unwrap();
+        fs::write(&path, b"weight").unwrap();
+        assert!(store.ensure(&PUBLISHED, &path).is_err());
+        assert!(server.ranges.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_offline_never_downloads() {
+        let dir = temp_dir("models-offline");
+        let server = serve(b"weights".to_vec(), None);
+        let path = dir.join("kokoro.onnx");
+
+        let err = store_for(&server.url).with_offline(true).ensure(&FILE, &path).unwrap_err();
+        assert!(err.contains("offline"), "{}", err);
+        assert!(server.ranges.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_mirror_url() {
+        let store = ModelStore::new().with_mirror("https://hf-mirror.com/");
+        assert_eq!(
+            store.url(&KOKORO_FP32),
+            "https://hf-mirror.com/hexgrad/Kokoro-82M/resolve/main/kokoro-v0_19.onnx"
+        );
+    }
+}
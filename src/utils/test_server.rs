@@ -0,0 +1,104 @@
+//! A local HTTP stand-in for download tests, serving one file with Range
+//! support.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub struct TestServer {
+    /// URL of the served file.
+    pub url: String,
+    /// `Range` header of every request received, `None` when absent.
+    pub ranges: Arc<Mutex<Vec<Option<String>>>>,
+}
+
+/// Serves `body` at `/model.onnx` until the test ends. With `cut_first_at`,
+/// the first response stops after that many bytes, as a dropped connection
+/// would.
+pub fn serve(body: Vec<u8>, cut_first_at: Option<usize>) -> TestServer {
+    serve_with(body, cut_first_at, false)
+}
+
+/// Serves `body` like `serve`, but answers every Range request with the
+/// whole file as a `206`, as a broken server or proxy would.
+pub fn serve_misaligned(body: Vec<u8>) -> TestServer {
+    serve_with(body, None, true)
+}
+
+fn serve_with(body: Vec<u8>, cut_first_at: Option<usize>, misaligned: bool) -> TestServer {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/model.onnx", listener.local_addr().unwrap());
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+
+    let seen = Arc::clone(&ranges);
+    thread::spawn(move || {
+        let mut cut = cut_first_at;
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { return };
+            respond(stream, &body, cut.take(), misaligned, &seen);
+        }
+    });
+    TestServer { url, ranges }
+}
+
+/// Answers one request, recording its `Range` header in `seen` before the
+/// response is written, so the client never sees the response first.
+fn respond(
+    mut stream: TcpStream,
+    body: &[u8],
+    cut: Option<usize>,
+    misaligned: bool,
+    seen: &Mutex<Vec<Option<String>>>,
+) {
+    let mut range = None;
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("range") {
+                range = Some(value.trim().to_string());
+            }
+        }
+    }
+    seen.lock().unwrap().push(range.clone());
+
+    let start = range
+        .as_deref()
+        .and_then(|r| r.strip_prefix("bytes="))
+        .and_then(|r| r.trim_end_matches('-').parse::<usize>().ok())
+        .map(|start| if misaligned { 0 } else { start });
+    let (status, headers, content) = match start {
+        Some(start) if start >= body.len() => (
+            "416 Range Not Satisfiable",
+            format!("Content-Range: bytes */{}\r\n", body.len()),
+            &body[..0],
+        ),
+        Some(start) => (
+            "206 Partial Content",
+            format!("Content-Range: bytes {}-{}/{}\r\n", start, body.len() - 1, body.len()),
+            &body[start..],
+        ),
+        None => ("200 OK", String::new(), body),
+    };
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+        status,
+        content.len(),
+        headers
+    );
+    let _ = stream.write_all(&content[..cut.unwrap_or(content.len()).min(content.len())]);
+}
+
+/// A fresh directory under the system temp dir for one test.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("koko-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}